@@ -0,0 +1,36 @@
+use crate::swap;
+use crate::Document;
+use crate::Position;
+
+/// An open document together with the view it had when it was last shown,
+/// so switching back to it restores the cursor and scroll position.
+#[derive(Default)]
+pub struct Buffer {
+    pub document: Document,
+    pub cursor_position: Position,
+    pub offset: Position,
+    /// The swap file next to the document belongs to another session, or to
+    /// one that died and hasn't been dealt with yet, so it is left alone.
+    pub foreign_swap: bool,
+}
+
+impl From<Document> for Buffer {
+    fn from(document: Document) -> Self {
+        let foreign_swap = document.file_name.as_deref().is_some_and(swap::exists);
+        Self {
+            document,
+            cursor_position: Position::default(),
+            offset: Position::default(),
+            foreign_swap,
+        }
+    }
+}
+
+impl Buffer {
+    pub fn name(&self) -> String {
+        self.document
+            .file_name
+            .clone()
+            .unwrap_or_else(|| "[NO NAME]".to_string())
+    }
+}
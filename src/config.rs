@@ -0,0 +1,89 @@
+const DEFAULT_TAB_WIDTH: usize = 8;
+
+/// What the gutter on the left of each window shows.
+#[derive(Default, PartialEq, Clone, Copy)]
+pub enum LineNumbers {
+    #[default]
+    Off,
+    Absolute,
+    /// Distance from the cursor line, with the cursor line itself numbered
+    /// absolutely.
+    Relative,
+}
+
+impl LineNumbers {
+    /// The mode that follows this one when cycling through them.
+    pub fn next(self) -> Self {
+        match self {
+            LineNumbers::Off => LineNumbers::Absolute,
+            LineNumbers::Absolute => LineNumbers::Relative,
+            LineNumbers::Relative => LineNumbers::Off,
+        }
+    }
+}
+
+/// Settings taken from the command line. Anything that is not an option is
+/// a file to open.
+pub struct Config {
+    pub files: Vec<String>,
+    pub tab_width: usize,
+    pub line_numbers: LineNumbers,
+    /// Wrap long rows onto several screen lines instead of scrolling
+    /// sideways.
+    pub wrap: bool,
+    /// Draw on the terminal's alternate screen so the shell's scrollback is
+    /// left as it was.
+    pub alternate_screen: bool,
+    /// Keep the previous version of a file as `file~` when saving.
+    pub backup: bool,
+    pub errors: Vec<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            files: Vec::new(),
+            tab_width: DEFAULT_TAB_WIDTH,
+            line_numbers: LineNumbers::default(),
+            wrap: false,
+            alternate_screen: true,
+            backup: false,
+            errors: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    pub fn from_args<I: Iterator<Item = String>>(mut args: I) -> Self {
+        let mut config = Self::default();
+        while let Some(arg) = args.next() {
+            let (name, inline_value) = match arg.split_once('=') {
+                Some((name, value)) if name.starts_with("--") => (name.to_string(), Some(value.to_string())),
+                _ => (arg.clone(), None),
+            };
+            match name.as_str() {
+                "--tab-width" => {
+                    let value = inline_value.or_else(|| args.next()).unwrap_or_default();
+                    match value.parse::<usize>() {
+                        Ok(width) if width > 0 => config.tab_width = width,
+                        _ => config.errors.push(format!("invalid tab width: {}", value)),
+                    }
+                },
+                "--line-numbers" => {
+                    let value = inline_value.or_else(|| args.next()).unwrap_or_default();
+                    match value.as_str() {
+                        "off" => config.line_numbers = LineNumbers::Off,
+                        "absolute" => config.line_numbers = LineNumbers::Absolute,
+                        "relative" => config.line_numbers = LineNumbers::Relative,
+                        _ => config.errors.push(format!("invalid line number mode: {}", value)),
+                    }
+                },
+                "--wrap" => config.wrap = true,
+                "--no-alternate-screen" => config.alternate_screen = false,
+                "--backup" => config.backup = true,
+                _ => config.files.push(arg),
+            }
+        }
+        config
+    }
+}
@@ -0,0 +1,163 @@
+/// Lines compared at most pairwise before giving up on finding the common
+/// lines in a changed region.
+const MAX_COMPARISONS: usize = 4_000_000;
+/// Unchanged lines shown around each change.
+const CONTEXT: usize = 3;
+
+#[derive(PartialEq, Clone, Copy)]
+enum Change<'a> {
+    Same(&'a str),
+    Removed(&'a str),
+    Added(&'a str),
+}
+
+/// Compares `old` and `new` line by line and returns the changes in the
+/// style of a unified diff, or `None` when they are the same.
+pub fn diff(old: &str, new: &str) -> Option<String> {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+    let changes = changes(&old, &new);
+    let changed: Vec<usize> = changes
+        .iter()
+        .enumerate()
+        .filter(|(_, change)| !matches!(change, Change::Same(_)))
+        .map(|(index, _)| index)
+        .collect();
+    if changed.is_empty() {
+        return None;
+    }
+    let mut result = Vec::new();
+    let mut old_line = 1;
+    let mut new_line = 1;
+    let mut last_shown = None;
+    for (index, change) in changes.iter().enumerate() {
+        let near = changed
+            .iter()
+            .any(|&at| index + CONTEXT >= at && index <= at + CONTEXT);
+        if near {
+            if last_shown.is_none_or(|last| last + 1 != index) {
+                result.push(format!("@@ -{} +{} @@", old_line, new_line));
+            }
+            last_shown = Some(index);
+            result.push(match change {
+                Change::Same(line) => format!("  {}", line),
+                Change::Removed(line) => format!("- {}", line),
+                Change::Added(line) => format!("+ {}", line),
+            });
+        }
+        match change {
+            Change::Same(_) => {
+                old_line += 1;
+                new_line += 1;
+            },
+            Change::Removed(_) => old_line += 1,
+            Change::Added(_) => new_line += 1,
+        }
+    }
+    Some(result.join("\n"))
+}
+
+/// The longest common subsequence of the lines, with the rest marked as
+/// removed or added. The common start and end are skipped first, and a
+/// middle too large to compare is reported as replaced entirely.
+fn changes<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<Change<'a>> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old_middle = &old[prefix..old.len() - suffix];
+    let new_middle = &new[prefix..new.len() - suffix];
+    let mut changes: Vec<Change> = old[..prefix].iter().map(|line| Change::Same(line)).collect();
+    if old_middle.len().saturating_mul(new_middle.len()) > MAX_COMPARISONS {
+        changes.extend(old_middle.iter().map(|line| Change::Removed(line)));
+        changes.extend(new_middle.iter().map(|line| Change::Added(line)));
+    } else {
+        changes.extend(common_lines(old_middle, new_middle));
+    }
+    changes.extend(old[old.len() - suffix..].iter().map(|line| Change::Same(line)));
+    changes
+}
+
+fn common_lines<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<Change<'a>> {
+    // lengths[i][j] is the length of the longest common subsequence of
+    // old[i..] and new[j..].
+    let width = new.len() + 1;
+    let mut lengths = vec![0; (old.len() + 1) * width];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lengths[i * width + j] = if old[i] == new[j] {
+                lengths[(i + 1) * width + j + 1] + 1
+            } else {
+                lengths[(i + 1) * width + j].max(lengths[i * width + j + 1])
+            };
+        }
+    }
+    let mut changes = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() && j < new.len() {
+        if old[i] == new[j] {
+            changes.push(Change::Same(old[i]));
+            i += 1;
+            j += 1;
+        } else if lengths[(i + 1) * width + j] >= lengths[i * width + j + 1] {
+            changes.push(Change::Removed(old[i]));
+            i += 1;
+        } else {
+            changes.push(Change::Added(new[j]));
+            j += 1;
+        }
+    }
+    changes.extend(old[i..].iter().map(|line| Change::Removed(line)));
+    changes.extend(new[j..].iter().map(|line| Change::Added(line)));
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identical_texts_have_no_diff() {
+        assert_eq!(diff("", ""), None);
+        assert_eq!(diff("one\ntwo\n", "one\ntwo\n"), None);
+    }
+
+    #[test]
+    fn shows_inserted_lines() {
+        assert_eq!(
+            diff("one\nthree\n", "one\ntwo\nthree\n").as_deref(),
+            Some("@@ -1 +1 @@\n  one\n+ two\n  three"),
+        );
+        assert_eq!(diff("", "one\n").as_deref(), Some("@@ -1 +1 @@\n+ one"));
+    }
+
+    #[test]
+    fn shows_deleted_lines() {
+        assert_eq!(
+            diff("one\ntwo\nthree\n", "one\nthree\n").as_deref(),
+            Some("@@ -1 +1 @@\n  one\n- two\n  three"),
+        );
+    }
+
+    #[test]
+    fn a_missing_final_newline_is_not_a_change() {
+        assert_eq!(diff("one\ntwo", "one\ntwo\n"), None);
+        assert_eq!(
+            diff("one\ntwo", "one\nthree").as_deref(),
+            Some("@@ -1 +1 @@\n  one\n- two\n+ three"),
+        );
+    }
+
+    #[test]
+    fn splits_distant_changes_into_hunks() {
+        let old: String = (1..=10).map(|line| format!("{}\n", line)).collect();
+        let new = old.replace("1\n", "one\n").replace("10\n", "ten\n");
+        assert_eq!(
+            diff(&old, &new).as_deref(),
+            Some("@@ -1 +1 @@\n- 1\n+ one\n  2\n  3\n  4\n@@ -7 +7 @@\n  7\n  8\n  9\n- 10\n+ ten"),
+        );
+    }
+}
@@ -1,31 +1,147 @@
+use crate::Encoding;
+use crate::FileFormat;
+use crate::LineEnding;
 use crate::FileType;
+use crate::History;
+use crate::Operation;
+use crate::PieceTable;
 use crate::SearchDirection;
 use crate::Position;
+use crate::Query;
 use crate::Row;
-use std::fs;
+use crate::highlighting;
+use std::collections::hash_map::DefaultHasher;
+use std::ffi::OsString;
+use std::fs::{self, Metadata, Permissions};
+use std::hash::Hasher;
 use std::io::{Error, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::SystemTime;
+use unicode_segmentation::UnicodeSegmentation;
 
+/// The text of a file together with what editing it needs. Every row of
+/// the text ends with a newline, whether or not the file's last line does,
+/// so an empty document has no rows at all.
 #[derive(Default)]
 pub struct Document {
-    rows: Vec<Row>,
+    text: PieceTable,
+    /// Highlighting of the rows from the top, kept between redraws and
+    /// dropped from the first row an edit touches.
+    highlights: Vec<Highlight>,
     pub file_name: Option<String>,
     dirty: bool,
     file_type: FileType,
+    history: History,
+    format: FileFormat,
+    /// The format of the file on disk, to tell whether a conversion still
+    /// needs saving.
+    saved_format: FileFormat,
+    /// Set for files that look binary, which are shown but never saved.
+    read_only: bool,
+    /// The file as it was last opened or saved, to notice other programs
+    /// changing it.
+    stamp: Option<FileStamp>,
+    /// Edits made since the editor last asked, to move the cursors of the
+    /// other windows on this document.
+    changes: Vec<Change>,
+}
+
+/// An edit as seen from positions elsewhere in the text: what lay between
+/// `start` and `old_end` now ends at `new_end`.
+#[derive(Clone, Copy)]
+pub struct Change {
+    start: Position,
+    old_end: Position,
+    new_end: Position,
+}
+
+impl Change {
+    /// Where `position` ends up after the edit. Positions before it stay,
+    /// positions in removed text move to where it was and positions after
+    /// it move along with the text.
+    pub fn shift(&self, position: &Position) -> Position {
+        let key = |at: &Position| (at.y, at.x);
+        if key(position) <= key(&self.start) {
+            *position
+        } else if key(position) < key(&self.old_end) {
+            self.start
+        } else if position.y == self.old_end.y {
+            Position {
+                x: self.new_end.x + (position.x - self.old_end.x),
+                y: self.new_end.y,
+            }
+        } else {
+            Position {
+                x: position.x,
+                y: position.y - self.old_end.y + self.new_end.y,
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct Highlight {
+    types: Vec<highlighting::Type>,
+    /// Cleared when a multiline comment is still open at the end of the
+    /// row, which leaves the row to be highlighted again.
+    done: bool,
+    /// Whether the row ends in the closing of a multiline comment.
+    closes_comment: bool,
+}
+
+struct FileStamp {
+    modified: Option<SystemTime>,
+    len: u64,
+    hash: u64,
+}
+
+impl FileStamp {
+    fn new(metadata: &Metadata, bytes: &[u8]) -> Self {
+        let mut hasher = DefaultHasher::new();
+        hasher.write(bytes);
+        Self {
+            modified: metadata.modified().ok(),
+            len: metadata.len(),
+            hash: hasher.finish(),
+        }
+    }
+
+    /// Reads the file as it is now.
+    fn read(file_name: &str) -> Result<Self, Error> {
+        let metadata = fs::metadata(file_name)?;
+        let bytes = fs::read(file_name)?;
+        Ok(Self::new(&metadata, &bytes))
+    }
 }
 
 impl Document {
     pub fn open(filename: &str) -> Result<Self, Error> {
-        let content = fs::read_to_string(filename)?;
+        let bytes = fs::read(filename)?;
+        let stamp = FileStamp::new(&fs::metadata(filename)?, &bytes);
+        let (encoding, mut content, binary) = Encoding::decode(bytes);
         let file_type = FileType::from(filename);
-        let mut rows = Vec::new();
-        for value in content.lines() {
-            rows.push(Row::from(value));
+        let format = FileFormat::detect(&content, encoding);
+        // Lines ending in CRLF in a file taken for LF keep their carriage
+        // return, so that saving writes them back as they were.
+        if format.line_ending == LineEnding::Crlf {
+            content = content.replace("\r\n", "\n");
+        }
+        if !content.is_empty() && !content.ends_with('\n') {
+            content.push('\n');
         }
         Ok(Self { 
-            rows, 
+            text: PieceTable::from(content),
+            highlights: Vec::new(),
             file_name: Some(filename.to_string()),
             dirty: false,
             file_type,
+            history: History::default(),
+            format,
+            saved_format: format,
+            read_only: binary,
+            stamp: Some(stamp),
+            changes: Vec::new(),
         })
     }
 
@@ -33,82 +149,323 @@ impl Document {
         self.file_type.name()
     }
 
-    pub fn row(&self, index: usize) -> Option<&Row> {
-        self.rows.get(index)
+    /// A read-only document showing `text`, for output such as diffs.
+    pub fn view(name: String, text: &str) -> Self {
+        let mut text = text.to_string();
+        if !text.is_empty() && !text.ends_with('\n') {
+            text.push('\n');
+        }
+        Self {
+            text: PieceTable::from(text),
+            file_name: Some(name),
+            read_only: true,
+            ..Self::default()
+        }
     }
 
-    pub fn is_empty(&self) -> bool {
-        self.rows.is_empty()
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
     }
 
-    pub fn len(&self) -> usize {
-        self.rows.len()
+    pub fn format(&self) -> FileFormat {
+        self.format
     }
 
-    fn insert_newline(&mut self, at: &Position) {
-        if at.y > self.rows.len() {
+    /// Changes how the document is written out on the next save.
+    pub fn set_format(&mut self, format: FileFormat) {
+        if self.read_only {
             return;
         }
-        if at.y == self.rows.len() {
-            self.rows.push(Row::default());
-            return;
+        self.format = format;
+        self.update_dirty();
+    }
+
+    fn update_dirty(&mut self) {
+        self.dirty = !self.history.is_saved() || self.format != self.saved_format;
+    }
+
+    pub fn row(&self, index: usize) -> Option<Row> {
+        if index >= self.len() {
+            return None;
         }
-        let current_row = &mut self.rows[at.y];
-        let new_row = current_row.split(at.x);
-        self.rows.insert(at.y + 1, new_row);
+        let row = Row::from(self.text.slice(self.text.line_range(index)));
+        let highlighting = self
+            .highlights
+            .get(index)
+            .map(|highlight| highlight.types.clone())
+            .unwrap_or_default();
+        Some(row.with_highlighting(highlighting))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn len(&self) -> usize {
+        // The text ends with a newline, after which the piece table counts
+        // one more, empty, line.
+        self.text.line_count() - 1
     }
 
     pub fn insert(&mut self, at: &Position, c: char) {
-        if at.y > self.rows.len() {
-            return;
+        self.insert_str(at, &c.to_string());
+    }
+
+    /// Inserts `text`, which may span several lines, as a single edit and
+    /// returns the position right after it.
+    pub fn insert_str(&mut self, at: &Position, text: &str) -> Position {
+        if self.read_only || at.y > self.len() || text.is_empty() {
+            return *at;
+        }
+        let end = end_of(at, text);
+        let mut text = text.to_string();
+        if at.y == self.len() && !text.ends_with('\n') {
+            // The cursor is on the line past the end of the file, which
+            // comes into existence with the text typed into it.
+            text.push('\n');
         }
+        self.insert_text(at, &text);
+        self.history.record(Operation::Insert { at: *at, text });
         self.dirty = true;
-        if c == '\n' {
-            self.insert_newline(at);
-        } else if at.y == self.rows.len() {
-            let mut row = Row::default();
-            row.insert(0, c);
-            self.rows.push(row);
+        end
+    }
+
+    pub fn delete(&mut self, at: &Position) {
+        let len = self.len();
+        if self.read_only || at.y >= len {
+            return;
+        }
+        let Some(row) = self.row(at.y) else {
+            return;
+        };
+        let text = if at.x == row.len() && at.y + 1 < len {
+            "\n".to_string()
+        } else if let Some(grapheme) = row.grapheme(at.x) {
+            grapheme.to_string()
         } else {
-            let row = &mut self.rows[at.y];
-            row.insert(at.x, c);
+            return;
+        };
+        self.remove_text(at, &end_of(at, &text));
+        self.history.record(Operation::Delete { at: *at, text });
+        self.dirty = true;
+    }
+
+    /// Removes the text between `start` and `end` as a single edit and
+    /// returns it.
+    pub fn delete_range(&mut self, start: &Position, end: &Position) -> String {
+        if self.read_only || start.y >= self.len() {
+            return String::new();
         }
-        self.unhighlight_rows(at.y);
+        let end = self.clamp(end);
+        let text = self.remove_text(start, &end);
+        if !text.is_empty() {
+            self.history.record(Operation::Delete { at: *start, text: text.clone() });
+            self.dirty = true;
+        }
+        text
     }
 
-    fn unhighlight_rows(&mut self, start: usize) {
-        let start = start.saturating_sub(1);
-        for row in self.rows.iter_mut().skip(start) {
-            row.is_highlighted = false;
+    /// Returns the text between `start` and `end`, rows joined by newlines.
+    pub fn text_range(&self, start: &Position, end: &Position) -> String {
+        if start.y >= self.len() {
+            return String::new();
         }
+        let start = self.offset(start);
+        let end = self.offset(&self.clamp(end));
+        self.text.slice(start..end.max(start))
     }
 
-    pub fn delete(&mut self, at: &Position) {
-        let len = self.rows.len();
-        if at.y >= len {
+    /// The whole text, rows joined by newlines.
+    pub fn text(&self) -> String {
+        self.text.slice(0..self.text.len().saturating_sub(1))
+    }
+
+    /// Replaces the whole text with `text`, rows joined by newlines, as a
+    /// single edit.
+    pub fn replace_text(&mut self, text: &str) {
+        let mut text = text.to_string();
+        if !text.is_empty() {
+            text.push('\n');
+        }
+        self.replace_rows(&text);
+    }
+
+    /// Replaces the text with `text`, which ends in a newline unless it is
+    /// empty, as a single edit. Only the rows that differ are replaced, so
+    /// the rows around them and positions on those stay where they are.
+    fn replace_rows(&mut self, text: &str) {
+        if self.read_only {
             return;
         }
-        self.dirty = true;
-        if at.x == self.rows[at.y].len() && at.y + 1 < len { 
-            let next_row = self.rows.remove(at.y + 1);
-            let row = &mut self.rows[at.y];
-            row.append(&next_row);
-        } else {
-            let row = &mut self.rows[at.y];
-            row.delete(at.x);
+        let old = self.text.slice(0..self.text.len());
+        let old_rows: Vec<&str> = old.split_inclusive('\n').collect();
+        let new_rows: Vec<&str> = text.split_inclusive('\n').collect();
+        let prefix = old_rows.iter().zip(&new_rows).take_while(|(a, b)| a == b).count();
+        let suffix = old_rows[prefix..]
+            .iter()
+            .rev()
+            .zip(new_rows[prefix..].iter().rev())
+            .take_while(|(a, b)| a == b)
+            .count();
+        let start = Position { x: 0, y: prefix };
+        let end = Position { x: 0, y: old_rows.len() - suffix };
+        self.begin_group();
+        let removed = self.remove_text(&start, &end);
+        if !removed.is_empty() {
+            self.history.record(Operation::Delete { at: start, text: removed });
+        }
+        self.insert_str(&start, &new_rows[prefix..new_rows.len() - suffix].concat());
+        self.end_group();
+        self.update_dirty();
+    }
+
+    /// Takes over the file as it is on disk now. The rows that changed are
+    /// replaced as one edit, which undo can take back.
+    pub fn reload(&mut self) -> Result<(), Error> {
+        let Some(file_name) = &self.file_name else {
+            return Ok(());
+        };
+        let on_disk = Document::open(file_name)?;
+        self.read_only = false;
+        self.replace_rows(&on_disk.text.slice(0..on_disk.text.len()));
+        self.read_only = on_disk.read_only;
+        self.format = on_disk.format;
+        self.saved_format = on_disk.saved_format;
+        self.stamp = on_disk.stamp;
+        self.history.mark_saved();
+        self.dirty = false;
+        Ok(())
+    }
+
+    /// Makes the edits until `end_group` undo as one step.
+    pub fn begin_group(&mut self) {
+        self.history.begin_group();
+    }
+
+    pub fn end_group(&mut self) {
+        self.history.end_group();
+    }
+
+    /// Moves a position past the last row back onto the end of the last row.
+    fn clamp(&self, at: &Position) -> Position {
+        match self.len().checked_sub(1) {
+            Some(last) if at.y > last => Position {
+                x: self.row(last).map_or(0, |row| row.len()),
+                y: last,
+            },
+            _ => *at,
+        }
+    }
+
+    /// Byte offset of `at` in the text. Positions on the line past the end
+    /// of the file are at the end of the text.
+    fn offset(&self, at: &Position) -> usize {
+        match self.row(at.y) {
+            Some(row) => self.text.line_range(at.y).start + row.byte_index(at.x),
+            None => self.text.len(),
+        }
+    }
+
+    /// Reverts the latest edit step and returns where the cursor should go.
+    pub fn undo(&mut self) -> Option<Position> {
+        let operations = self.history.undo()?;
+        let mut cursor = None;
+        for operation in operations.iter().rev() {
+            match operation {
+                Operation::Insert { at, text } => {
+                    self.remove_text(at, &end_of(at, text));
+                    cursor = Some(*at);
+                },
+                Operation::Delete { at, text } => {
+                    self.insert_text(at, text);
+                    cursor = Some(*at);
+                },
+            }
+        }
+        self.update_dirty();
+        cursor
+    }
+
+    /// Re-applies the latest undone step and returns where the cursor should go.
+    pub fn redo(&mut self) -> Option<Position> {
+        let operations = self.history.redo()?;
+        let mut cursor = None;
+        for operation in &operations {
+            match operation {
+                Operation::Insert { at, text } => cursor = Some(self.insert_text(at, text)),
+                Operation::Delete { at, text } => {
+                    self.remove_text(at, &end_of(at, text));
+                    cursor = Some(*at);
+                },
+            }
         }
+        self.update_dirty();
+        cursor
+    }
+
+    fn insert_text(&mut self, at: &Position, text: &str) -> Position {
+        if at.y > self.len() {
+            return *at;
+        }
+        let offset = self.offset(at);
+        self.text.insert(offset, text);
         self.unhighlight_rows(at.y);
+        let end = end_of(at, text);
+        self.changes.push(Change {
+            start: *at,
+            old_end: *at,
+            new_end: end,
+        });
+        end
+    }
+
+    fn remove_text(&mut self, start: &Position, end: &Position) -> String {
+        if start.y >= self.len() {
+            return String::new();
+        }
+        let from = self.offset(start);
+        let to = self.offset(end);
+        let removed = self.text.delete(from..to.max(from));
+        self.unhighlight_rows(start.y);
+        self.changes.push(Change {
+            start: *start,
+            old_end: end_of(start, &removed),
+            new_end: *start,
+        });
+        removed
+    }
+
+    /// The edits made since the last call, oldest first.
+    pub fn take_changes(&mut self) -> Vec<Change> {
+        std::mem::take(&mut self.changes)
+    }
+
+    fn unhighlight_rows(&mut self, start: usize) {
+        self.highlights.truncate(start.saturating_sub(1));
     }
 
-    pub fn save(&mut self) -> Result<(), Error> {
+    /// Writes the document to its file. With `backup` the previous version
+    /// of the file is kept next to it, with `~` appended to its name.
+    pub fn save(&mut self, backup: bool) -> Result<(), Error> {
+        if self.read_only {
+            return Err(Error::other("the file is read-only"));
+        }
         if let Some(file_name) = &self.file_name {
-            let mut file = fs::File::create(file_name)?;
-            self.file_type = FileType::from(file_name);
-            for row in &mut self.rows {
-                file.write_all(row.as_bytes())?;
-                file.write_all(b"\n")?;
+            let mut text = if self.format.final_newline {
+                self.text.slice(0..self.text.len())
+            } else {
+                self.text()
+            };
+            if self.format.line_ending != LineEnding::Lf {
+                text = text.replace('\n', self.format.line_ending.as_str());
             }
+            let bytes = self.format.encoding.encode(&text)?;
+            write_atomically(Path::new(file_name), &bytes, backup, None)?;
+            self.stamp = fs::metadata(file_name).ok().map(|metadata| FileStamp::new(&metadata, &bytes));
+            self.file_type = FileType::from(file_name);
             self.dirty = false;
+            self.history.mark_saved();
+            self.saved_format = self.format;
         }
         Ok(())
     }
@@ -117,8 +474,81 @@ impl Document {
         self.dirty
     }
 
-    pub fn find(&self, query: &str, at: &Position, direction: SearchDirection) -> Option<Position> {
-        if at.y >= self.rows.len() { 
+    /// Whether another program changed the file since it was last opened or
+    /// saved. Only changed content counts: a file that was merely touched is
+    /// taken as it is. A file that has gone missing is not reported either,
+    /// since saving loses nothing then.
+    pub fn changed_on_disk(&mut self) -> bool {
+        let (Some(file_name), Some(stamp)) = (&self.file_name, &self.stamp) else {
+            return false;
+        };
+        let Ok(metadata) = fs::metadata(file_name) else {
+            return false;
+        };
+        if metadata.modified().ok() == stamp.modified && metadata.len() == stamp.len {
+            return false;
+        }
+        let Ok(current) = FileStamp::read(file_name) else {
+            return false;
+        };
+        let changed = current.hash != stamp.hash;
+        if !changed {
+            self.stamp = Some(current);
+        }
+        changed
+    }
+
+    /// Takes the file on disk as the one last saved, so that its changes are
+    /// not reported again.
+    pub fn ignore_disk_changes(&mut self) {
+        if let Some(file_name) = &self.file_name {
+            self.stamp = FileStamp::read(file_name).ok();
+        }
+    }
+
+    /// Finds the next match in `direction`, continuing from the other end of
+    /// the document when there is none before it. The flag tells whether the
+    /// search wrapped around.
+    pub fn find(
+        &self,
+        query: &Query,
+        at: &Position,
+        direction: SearchDirection,
+    ) -> Option<(Position, bool)> {
+        if let Some(position) = self.find_from(query, at, direction) {
+            return Some((position, false));
+        }
+        let restart = if direction == SearchDirection::Forward {
+            Position::default()
+        } else {
+            let y = self.len().checked_sub(1)?;
+            Position { x: self.row(y)?.len(), y }
+        };
+        self.find_from(query, &restart, direction)
+            .map(|position| (position, true))
+    }
+
+    /// Position of the match at `at` among all matches of `query`, counted
+    /// from 1, together with the total number of matches.
+    pub fn count_matches(&self, query: &Query, at: &Position) -> (Option<usize>, usize) {
+        let mut current = None;
+        let mut total = 0;
+        for y in 0..self.len() {
+            let Some(row) = self.row(y) else {
+                break;
+            };
+            for (start, _) in row.find_all(query) {
+                total += 1;
+                if y == at.y && start == at.x {
+                    current = Some(total);
+                }
+            }
+        }
+        (current, total)
+    }
+
+    fn find_from(&self, query: &Query, at: &Position, direction: SearchDirection) -> Option<Position> {
+        if at.y >= self.len() { 
             return None;
         }
 
@@ -130,14 +560,14 @@ impl Document {
         };
 
         let end = if direction == SearchDirection::Forward {
-            self.rows.len()
+            self.len()
         } else {
             at.y.saturating_add(1)
         };
 
         for _ in start..end {
-            if let Some(row) = self.rows.get(position.y) {
-                if let Some(x) = row.find(&query, position.x, direction) {
+            if let Some(row) = self.row(position.y) {
+                if let Some(x) = row.find(query, position.x, direction) {
                     position.x = x;
                     return Some(position);
                 }
@@ -147,7 +577,7 @@ impl Document {
                     position.x = 0;
                 } else {
                     position.y = position.y.saturating_sub(1);
-                    position.x = self.rows[position.y].len();
+                    position.x = self.row(position.y).map_or(0, |row| row.len());
                 }
             } else {
                 return None;
@@ -156,23 +586,325 @@ impl Document {
         None
     }
 
-    pub fn highlight(&mut self, word: &Option<String>, until: Option<usize>) {
+    /// Finds the first match at or after `at` and returns where it starts
+    /// and ends.
+    pub fn find_range(&self, query: &Query, at: &Position) -> Option<(Position, Position)> {
+        let mut x = at.x;
+        for y in at.y..self.len() {
+            let row = self.row(y)?;
+            let found = row
+                .find_all(query)
+                .into_iter()
+                .find(|(start, _)| *start >= x);
+            if let Some((start, end)) = found {
+                return Some((Position { x: start, y }, Position { x: end, y }));
+            }
+            x = 0;
+        }
+        None
+    }
+
+    pub fn highlight(&mut self, word: &Option<Query>, until: Option<usize>) {
         let mut start_with_comment = false;
         let until = if let Some(until) = until {
-            if until.saturating_add(1) < self.rows.len() {
+            if until.saturating_add(1) < self.len() {
                 until.saturating_add(1)
             } else {
-                self.rows.len()
+                self.len()
             }
         } else {
-            self.rows.len()
+            self.len()
         };
-        for row in &mut self.rows[..until] {
+        if self.highlights.len() < until {
+            self.highlights.resize_with(until, Highlight::default);
+        }
+        for y in 0..until {
+            let highlight = &self.highlights[y];
+            if highlight.done && word.is_none() {
+                start_with_comment = highlight.closes_comment;
+                continue;
+            }
+            let Some(mut row) = self.row(y) else {
+                break;
+            };
             start_with_comment = row.highlight(
                 self.file_type.highlighting_options(), 
                 word,
                 start_with_comment,
             );
+            let types = row.take_highlighting();
+            self.highlights[y] = Highlight {
+                closes_comment: types.last() == Some(&highlighting::Type::MultilineComment)
+                    && row.as_str().ends_with("*/"),
+                types,
+                done: !start_with_comment,
+            };
         }
     }
 }
+
+/// Position right after `text` when it is inserted at `at`.
+fn end_of(at: &Position, text: &str) -> Position {
+    match text.rfind('\n') {
+        Some(index) => Position {
+            x: text[index + 1..].graphemes(true).count(),
+            y: at.y.saturating_add(text.matches('\n').count()),
+        },
+        None => Position {
+            x: at.x.saturating_add(text.graphemes(true).count()),
+            y: at.y,
+        },
+    }
+}
+
+/// Replaces the file at `path` with `bytes` so that it holds either the old
+/// or the new contents even if writing fails halfway: the bytes go to a
+/// temporary file in the same directory, which is synced and then renamed
+/// over the target. The file gets `permissions`, or keeps the target's
+/// without any.
+pub fn write_atomically(
+    path: &Path,
+    bytes: &[u8],
+    backup: bool,
+    permissions: Option<Permissions>,
+) -> Result<(), Error> {
+    // Write through symbolic links instead of replacing them.
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let name = path.file_name().ok_or_else(|| Error::other("not a file name"))?;
+    let mut temp_name = OsString::from(".");
+    temp_name.push(name);
+    temp_name.push(format!(".tte-{}", process::id()));
+    let temp = directory.join(temp_name);
+    let written = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&temp)
+        .and_then(|mut file| {
+            // Before any of the contents is written, so that a private file
+            // is never readable by others through the temporary one.
+            if let Some(permissions) = permissions.or_else(|| fs::metadata(&path).ok().map(|metadata| metadata.permissions())) {
+                file.set_permissions(permissions)?;
+            }
+            file.write_all(bytes)?;
+            file.sync_all()
+        });
+    if let Err(error) = written {
+        let _ = fs::remove_file(&temp);
+        return Err(error);
+    }
+    if backup && path.exists() {
+        let mut backup_path = path.clone().into_os_string();
+        backup_path.push("~");
+        if let Err(error) = fs::copy(&path, backup_path) {
+            let _ = fs::remove_file(&temp);
+            return Err(error);
+        }
+    }
+    if let Err(error) = fs::rename(&temp, &path) {
+        let _ = fs::remove_file(&temp);
+        return Err(error);
+    }
+    // Sync the directory too, so that the rename itself survives a crash.
+    if let Ok(directory) = fs::File::open(&directory) {
+        let _ = directory.sync_all();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An empty directory of its own for test `name`.
+    fn temp_dir(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("tte-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    fn document(text: &str) -> Document {
+        Document {
+            text: PieceTable::from(format!("{}\n", text)),
+            ..Document::default()
+        }
+    }
+
+    #[test]
+    fn undoing_the_first_edit_leaves_an_empty_document() {
+        let mut document = Document::default();
+        document.insert(&Position::default(), 'a');
+        assert_eq!(document.len(), 1);
+        assert!(document.is_dirty());
+        document.undo();
+        assert!(document.is_empty());
+        assert!(!document.is_dirty());
+        document.redo();
+        assert_eq!(document.text(), "a");
+    }
+
+    #[test]
+    fn edits_across_rows() {
+        let mut document = document("one\ntwo\nthree");
+        let end = document.insert_str(&Position { x: 1, y: 1 }, "X\nY");
+        assert!(end == Position { x: 1, y: 2 });
+        assert_eq!(document.text(), "one\ntX\nYwo\nthree");
+        let removed = document.delete_range(&Position { x: 2, y: 0 }, &Position { x: 1, y: 2 });
+        assert_eq!(removed, "e\ntX\nY");
+        assert_eq!(document.text(), "onwo\nthree");
+        assert_eq!(document.len(), 2);
+        document.delete(&Position { x: 4, y: 0 });
+        assert_eq!(document.text(), "onwothree");
+        document.undo();
+        document.undo();
+        document.undo();
+        assert_eq!(document.text(), "one\ntwo\nthree");
+    }
+
+    #[test]
+    fn typing_on_the_line_past_the_end_adds_a_row() {
+        let mut document = document("one");
+        document.insert(&Position { x: 0, y: 1 }, 'x');
+        assert_eq!(document.len(), 2);
+        assert_eq!(document.text(), "one\nx");
+        document.undo();
+        assert_eq!(document.len(), 1);
+    }
+
+    #[test]
+    fn undoing_a_new_line_on_an_empty_document_removes_it() {
+        let mut document = Document::default();
+        document.insert(&Position::default(), '\n');
+        assert_eq!(document.len(), 1);
+        document.undo();
+        assert!(document.is_empty());
+        assert!(!document.is_dirty());
+    }
+
+    #[test]
+    fn positions_follow_edits_elsewhere() {
+        let mut document = Document::default();
+        document.insert_str(&Position::default(), "one\ntwo\nthree");
+        document.take_changes();
+        let after = Position { x: 3, y: 1 };
+        let on_next_row = Position { x: 2, y: 2 };
+        document.insert_str(&Position { x: 1, y: 1 }, "X\nY");
+        let changes = document.take_changes();
+        assert!(changes[0].shift(&after) == Position { x: 3, y: 2 });
+        assert!(changes[0].shift(&on_next_row) == Position { x: 2, y: 3 });
+        assert!(changes[0].shift(&Position { x: 1, y: 0 }) == Position { x: 1, y: 0 });
+        document.delete_range(&Position { x: 2, y: 0 }, &Position { x: 1, y: 2 });
+        let changes = document.take_changes();
+        assert!(changes[0].shift(&Position { x: 0, y: 1 }) == Position { x: 2, y: 0 });
+        assert!(changes[0].shift(&Position { x: 3, y: 2 }) == Position { x: 4, y: 0 });
+        assert!(changes[0].shift(&Position { x: 2, y: 3 }) == Position { x: 2, y: 1 });
+    }
+
+    #[test]
+    fn views_cannot_be_edited() {
+        let mut document = Document::view("test".to_string(), "one\ntwo");
+        let start = Position::default();
+        assert!(document.insert_str(&start, "x") == start);
+        document.insert(&Position { x: 0, y: 2 }, 'x');
+        document.delete(&start);
+        assert_eq!(document.delete_range(&start, &Position { x: 1, y: 1 }), "");
+        document.replace_text("other");
+        assert_eq!(document.text(), "one\ntwo");
+        assert!(!document.is_dirty());
+    }
+
+    #[test]
+    fn replacing_the_text_keeps_the_rows_around_the_change() {
+        let mut document = document("one\ntwo\nthree");
+        document.replace_text("one\n2\n2.5\nthree");
+        assert_eq!(document.text(), "one\n2\n2.5\nthree");
+        let changes = document.take_changes();
+        let follow = |at: Position| changes.iter().fold(at, |at, change| change.shift(&at));
+        assert!(follow(Position { x: 2, y: 0 }) == Position { x: 2, y: 0 });
+        assert!(follow(Position { x: 3, y: 2 }) == Position { x: 3, y: 3 });
+        document.replace_text("");
+        assert!(document.is_empty());
+        document.undo();
+        document.undo();
+        assert_eq!(document.text(), "one\ntwo\nthree");
+        assert!(!document.is_dirty());
+    }
+
+    #[test]
+    fn saving_writes_the_line_endings_back_as_they_were() {
+        let directory = temp_dir("line-endings");
+        let path = directory.join("file.txt");
+        let files: [&[u8]; 3] = [b"one\ntwo\r\nthree\n", b"one\r\ntwo\r\nthree\r\n", b"one\r\ntwo"];
+        for bytes in files {
+            fs::write(&path, bytes).unwrap();
+            let mut document = Document::open(path.to_str().unwrap()).unwrap();
+            assert!(!document.is_dirty());
+            document.save(false).unwrap();
+            assert_eq!(fs::read(&path).unwrap(), bytes);
+        }
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    /// The names in `directory`, sorted, to check that no temporary file is
+    /// left behind.
+    fn file_names(directory: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn writing_atomically_keeps_the_permissions_and_a_backup() {
+        use std::os::unix::fs::PermissionsExt;
+        let directory = temp_dir("write-atomically");
+        let path = directory.join("file.txt");
+        fs::write(&path, "old").unwrap();
+        fs::set_permissions(&path, Permissions::from_mode(0o640)).unwrap();
+        write_atomically(&path, b"new", true, None).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o640);
+        assert_eq!(fs::read_to_string(directory.join("file.txt~")).unwrap(), "old");
+        assert_eq!(file_names(&directory), ["file.txt", "file.txt~"]);
+        // Given permissions win over those of the file being replaced.
+        write_atomically(&path, b"newer", false, Some(Permissions::from_mode(0o600))).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        assert_eq!(fs::read_to_string(directory.join("file.txt~")).unwrap(), "old");
+        assert_eq!(file_names(&directory), ["file.txt", "file.txt~"]);
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn writing_atomically_goes_through_symbolic_links() {
+        let directory = temp_dir("write-through-link");
+        let target = directory.join("target.txt");
+        let link = directory.join("link.txt");
+        fs::write(&target, "old").unwrap();
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+        write_atomically(&link, b"new", false, None).unwrap();
+        assert!(fs::symlink_metadata(&link).unwrap().file_type().is_symlink());
+        assert_eq!(fs::read_to_string(&target).unwrap(), "new");
+        assert_eq!(file_names(&directory), ["link.txt", "target.txt"]);
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn writing_atomically_removes_the_temporary_file_on_failure() {
+        let directory = temp_dir("write-failure");
+        // A file cannot be renamed over a directory.
+        let path = directory.join("file.txt");
+        fs::create_dir(&path).unwrap();
+        fs::write(path.join("inside"), "").unwrap();
+        assert!(write_atomically(&path, b"new", false, None).is_err());
+        assert_eq!(file_names(&directory), ["file.txt"]);
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -1,17 +1,43 @@
+use crate::Buffer;
+use crate::Config;
 use crate::Document;
+use crate::Frame;
+use crate::Layout;
+use crate::LineEnding;
+use crate::LineNumbers;
+use crate::Query;
+use crate::Rect;
+use crate::Replacer;
 use crate::Row;
+use crate::SearchOptions;
+use crate::SplitDirection;
 use crate::Terminal;
+use crate::Window;
+use crate::diff;
+use crate::swap;
 use std::env;
 use std::io::Error;
+use std::iter;
+use std::ops::Range;
 use std::time::Duration;
 use std::time::Instant;
-use crossterm::event::{ KeyEvent, KeyCode, KeyModifiers };
+use crossterm::event::{ Event, KeyEvent, KeyCode, KeyModifiers, MouseButton, MouseEvent, MouseEventKind };
 use crossterm::style::Color;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
 const STATUS_FG_COLOR:Color = Color::Rgb { r: 63, g: 63, b: 63 };
 const STATUS_BG_COLOR:Color = Color::Rgb { r: 239, g: 239, b: 239 };
+const INACTIVE_STATUS_BG_COLOR:Color = Color::Rgb { r: 160, g: 160, b: 160 };
+const LINE_NUMBER_FG_COLOR:Color = Color::Rgb { r: 128, g: 128, b: 128 };
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 const QUIT_TIMES: u8 = 3;
+const WHEEL_LINES: usize = 3;
+/// How long typing has to pause before the swap files are brought up to date.
+const SWAP_DELAY: Duration = Duration::from_secs(2);
+/// How long the swap files may fall behind while typing goes on without pause.
+const SWAP_INTERVAL: Duration = Duration::from_secs(10);
+/// How often files are checked for changes by other programs while idle.
+const DISK_CHECK_INTERVAL: Duration = Duration::from_secs(2);
 
 #[derive(PartialEq, Copy, Clone)]
 pub enum SearchDirection {
@@ -19,7 +45,7 @@ pub enum SearchDirection {
     Backward,
 }
 
-#[derive(Default, Clone, Copy)]
+#[derive(Default, PartialEq, Clone, Copy)]
 pub struct Position {
     pub x: usize,
     pub y: usize,
@@ -42,43 +68,247 @@ impl StatusMessage {
 pub struct Editor {
     should_quit: bool,
     terminal: Terminal,
-    cursor_position: Position,
-    offset: Position,
-    document: Document,
+    buffers: Vec<Buffer>,
+    windows: Vec<Window>,
+    active_window: usize,
+    layout: Layout,
     status_message: StatusMessage,
     quit_times: u8,
-    highlighted_word: Option<String>,
+    highlighted_word: Option<Query>,
+    search_options: SearchOptions,
+    search_wrapped: bool,
+    clipboard: Option<String>,
+    config: Config,
+    /// Set by any input, so that the swap files are written once it pauses.
+    swap_pending: bool,
+    swap_written: Instant,
 }
 
-impl Editor {
-    pub fn default() -> Self {
-        let args: Vec<String> = env::args().collect();
-        let mut initial_status = String::from("HELP: Ctrl-F = find | Ctrl-S = save | Ctrl-Q = quit");
-        let document = if let Some(file_name) = args.get(1) {
-            let doc = Document::open(file_name);
-            if let Ok(doc) = doc {
-                doc
+impl Default for Editor {
+    fn default() -> Self {
+        let config = Config::from_args(env::args().skip(1));
+        let mut initial_status = String::from("HELP: Ctrl-F = find | Ctrl-R = replace | Ctrl-S = save | Ctrl-O = open | Ctrl-N/P/B = next/previous/list buffers | Alt-S/V/Q/W = split/vsplit/close/next pane | Ctrl-C/X/V = copy/cut/paste | Ctrl-Z/Y = undo/redo | Alt-L = line numbers | Alt-Z = wrap | Alt-E/N = line endings/final newline | Ctrl-Q = quit");
+        let mut buffers = Vec::new();
+        let mut failed = Vec::new();
+        let mut binary = Vec::new();
+        for file_name in &config.files {
+            if let Ok(doc) = Document::open(file_name) {
+                if doc.is_read_only() {
+                    binary.push(file_name.clone());
+                }
+                buffers.push(Buffer::from(doc));
             } else {
-                initial_status = format!("ERR: Could not open file: {}", file_name);
-                Document::default()
+                failed.push(file_name.clone());
             }
-        } else {
-            Document::default()
-        };
+        }
+        if !binary.is_empty() {
+            initial_status = format!("WARNING: Binary files opened read-only: {}", binary.join(", "));
+        }
+        if !failed.is_empty() {
+            initial_status = format!("ERR: Could not open file: {}", failed.join(", "));
+        }
+        if !config.errors.is_empty() {
+            initial_status = format!("ERR: {}", config.errors.join(", "));
+        }
+        if buffers.is_empty() {
+            buffers.push(Buffer::default());
+        }
 
-        Self { 
+        let mut editor = Self { 
             should_quit: false, 
-            terminal: Terminal::default().expect("Failed to initialize terminal"),
-            document,
-            cursor_position: Position::default(),
-            offset: Position::default(),
+            terminal: Terminal::new(config.alternate_screen).expect("Failed to initialize terminal"),
+            buffers,
+            windows: vec![Window::new(0)],
+            active_window: 0,
+            layout: Layout::Window(0),
             status_message: StatusMessage::from(initial_status),
             quit_times: QUIT_TIMES,
             highlighted_word: None,
+            search_options: SearchOptions::default(),
+            search_wrapped: false,
+            clipboard: None,
+            config,
+            swap_pending: false,
+            swap_written: Instant::now(),
+        };
+        editor.arrange_windows();
+        editor
+    }
+}
+
+impl Editor {
+    fn window(&self) -> &Window {
+        &self.windows[self.active_window]
+    }
+
+    fn window_mut(&mut self) -> &mut Window {
+        &mut self.windows[self.active_window]
+    }
+
+    fn document(&self) -> &Document {
+        &self.buffers[self.window().buffer].document
+    }
+
+    fn document_mut(&mut self) -> &mut Document {
+        let buffer = self.window().buffer;
+        &mut self.buffers[buffer].document
+    }
+
+    /// Screen column of the cursor in the active window.
+    fn cursor_column(&self) -> usize {
+        let Position { x, y } = self.window().cursor_position;
+        self.document()
+            .row(y)
+            .map_or(0, |row| row.render_x(x, self.config.tab_width))
+    }
+
+    /// Column ranges of the screen lines row `y` takes in `window`. Without
+    /// soft wrapping every row is a single line.
+    fn screen_lines(&self, window: &Window, y: usize) -> Vec<Range<usize>> {
+        let width = if self.config.wrap {
+            window.text_width()
+        } else {
+            usize::MAX
+        };
+        match self.buffers[window.buffer].document.row(y) {
+            Some(row) => row.wrap(width, self.config.tab_width),
+            None => Row::default().wrap(width, self.config.tab_width),
+        }
+    }
+
+    /// Screen line of the cursor within its row, and the cursor's column
+    /// within that line.
+    fn cursor_line(&self) -> (usize, usize) {
+        let column = self.cursor_column();
+        let lines = self.screen_lines(self.window(), self.window().cursor_position.y);
+        let line = lines
+            .iter()
+            .rposition(|range| range.start <= column)
+            .unwrap_or(0);
+        (line, column.saturating_sub(lines[line].start))
+    }
+
+    /// Screen position of the cursor in the active window, or `None`
+    /// when the view has been scrolled away from the cursor.
+    fn cursor_screen_position(&self) -> Option<Position> {
+        let window = self.window();
+        let Position { x, y } = if self.config.wrap {
+            if window.cursor_position.y < window.offset.y {
+                return None;
+            }
+            let (line, column) = self.cursor_line();
+            let mut y = line;
+            for row in window.offset.y..window.cursor_position.y {
+                y += self.screen_lines(window, row).len();
+            }
+            Position {
+                x: column.min(window.text_width().saturating_sub(1)),
+                y: y.checked_sub(window.top_line)?,
+            }
+        } else {
+            Position {
+                x: self.cursor_column().checked_sub(window.offset.x)?,
+                y: window.cursor_position.y.checked_sub(window.offset.y)?,
+            }
+        };
+        (x < window.text_width() && y < window.area.height).then_some(Position {
+            x: window.area.x + window.gutter + x,
+            y: window.area.y + y,
+        })
+    }
+
+    fn scroll(&mut self) {
+        // The gutter grows with the document, so re-measure it first.
+        self.arrange_windows();
+        if self.config.wrap {
+            self.scroll_wrapped();
+            return;
+        }
+        let x = self.cursor_column();
+        let window = self.window_mut();
+        window.top_line = 0;
+        window.scroll(x);
+    }
+
+    /// Scrolls every window so that its cursor stays visible, for when
+    /// their sizes change.
+    fn scroll_windows(&mut self) {
+        let active = self.active_window;
+        for index in 0..self.windows.len() {
+            self.active_window = index;
+            self.scroll();
+        }
+        self.active_window = active;
+    }
+
+    /// Scrolls by screen lines so that the cursor stays visible while rows
+    /// are wrapped.
+    fn scroll_wrapped(&mut self) {
+        let (line, _) = self.cursor_line();
+        let window = self.window();
+        let cursor = (window.cursor_position.y, line);
+        let top_lines = self.screen_lines(window, window.offset.y).len();
+        let mut top = (window.offset.y, window.top_line.min(top_lines - 1));
+        if cursor < top {
+            top = cursor;
+        } else {
+            // Walk up from the cursor to the first line that would still
+            // show it at the bottom of the window.
+            let mut first = cursor;
+            for _ in 1..window.area.height {
+                if first <= top {
+                    break;
+                }
+                first = if first.1 > 0 {
+                    (first.0, first.1 - 1)
+                } else {
+                    (first.0 - 1, self.screen_lines(window, first.0 - 1).len() - 1)
+                };
+            }
+            top = top.max(first);
         }
+        let window = self.window_mut();
+        window.offset = Position { x: 0, y: top.0 };
+        window.top_line = top.1;
+    }
+
+    /// Gives every window its place on the screen, following the layout and
+    /// the current terminal size.
+    fn arrange_windows(&mut self) {
+        let size = self.terminal.size();
+        let area = Rect {
+            x: 0,
+            y: 0,
+            width: size.width as usize,
+            height: (size.height as usize).saturating_add(1),
+        };
+        let mut areas = Vec::new();
+        self.layout.arrange(area, &mut areas, &mut Vec::new());
+        for (index, area) in areas {
+            let gutter = self.gutter_width(&self.buffers[self.windows[index].buffer].document);
+            let window = &mut self.windows[index];
+            window.area = Rect {
+                height: area.height.saturating_sub(1),
+                ..area
+            };
+            // Give up on the gutter in windows too narrow to show any text.
+            window.gutter = if gutter < area.width { gutter } else { 0 };
+        }
+    }
+
+    /// Width of the line number gutter for `document`: enough digits for its
+    /// last line plus a space before the text.
+    fn gutter_width(&self, document: &Document) -> usize {
+        if self.config.line_numbers == LineNumbers::Off {
+            return 0;
+        }
+        document.len().max(1).to_string().len().saturating_add(1)
     }
 
     pub fn run(&mut self) -> Result<(), Error> {
+        for index in 0..self.buffers.len() {
+            self.check_swap(index);
+        }
         loop {
             if let Err(error) = self.refresh_screen() {
                 die(error);
@@ -94,79 +324,651 @@ impl Editor {
     }
 
     fn refresh_screen(&mut self) -> Result<(), Error> {
-        Terminal::cursor_hide();
-        Terminal::cursor_position(&Position::default());
         if self.should_quit {
-            Terminal::clear_screen();
-            println!("Bye bye!\r");
-        } else {
-            self.document.highlight(
+            if self.config.alternate_screen {
+                Terminal::clear_screen();
+                Terminal::cursor_position(&Position::default());
+                println!("Bye bye!\r");
+            } else {
+                // The last screen stays in the scrollback, with the shell
+                // going on below it.
+                let last_line = (self.terminal.size().height as usize).saturating_add(1);
+                Terminal::cursor_position(&Position { x: 0, y: last_line });
+                println!("\r");
+            }
+            return Terminal::flush();
+        }
+        self.follow_edits(Some(self.active_window));
+        self.arrange_windows();
+        for window in &self.windows {
+            self.buffers[window.buffer].document.highlight(
                 &self.highlighted_word,
-                Some(
-                    self.offset
-                        .y
-                        .saturating_add(self.terminal.size().height as usize),
-                ),
+                Some(window.offset.y.saturating_add(window.area.height)),
             );
-            self.draw_rows();
-            self.draw_status_bar();
-            self.draw_message_bar();
-            Terminal::cursor_position(&Position {
-                x: self.cursor_position.x.saturating_sub(self.offset.x),
-                y: self.cursor_position.y.saturating_sub(self.offset.y),
-            });
         }
-        Terminal::cursor_show();
-        Terminal::flush()
+        let size = self.terminal.size();
+        let mut frame = Frame::new(size.width as usize, (size.height as usize).saturating_add(2));
+        for index in 0..self.windows.len() {
+            self.draw_window(&mut frame, index);
+        }
+        self.draw_separators(&mut frame);
+        self.draw_message_bar(&mut frame);
+        frame.set_cursor(self.cursor_screen_position());
+        self.terminal.draw(frame)
     }
 
     fn save(&mut self) {
-        if self.document.file_name.is_none() {
-            let new_name:Option<String> = self.prompt("Save as: ", |_, _, _| {}).unwrap_or(None);
+        if self.document().file_name.is_none() {
+            let new_name:Option<String> = self
+                .prompt(|_, _| "Save as: ".to_string(), |_, _, _| {})
+                .unwrap_or(None)
+                .filter(|name| !name.is_empty());
             if new_name.is_none() {
                 self.status_message = StatusMessage::from("Save aborted.".to_string());
                 return;
             }
-            self.document.file_name = new_name;
+            self.document_mut().file_name = new_name;
+        }
+
+        let index = self.window().buffer;
+        if self.buffers[index].document.changed_on_disk() {
+            self.resolve_conflict(index, true);
+        } else {
+            self.write_buffer(index);
+        }
+    }
+
+    fn write_buffer(&mut self, index: usize) {
+        let backup = self.config.backup;
+        let buffer = &mut self.buffers[index];
+        match buffer.document.save(backup) {
+            Ok(()) => {
+                if let (Some(file_name), false) = (&buffer.document.file_name, buffer.foreign_swap) {
+                    swap::remove(file_name);
+                }
+                self.status_message = StatusMessage::from("File saved successfully.".to_string());
+            },
+            Err(error) => self.status_message = StatusMessage::from(format!("Error writing file: {}", error)),
         }
+    }
 
-        if self.document.save().is_ok() {
-            self.status_message = StatusMessage::from("File saved successfully.".to_string());
+    fn open(&mut self) {
+        let file_name = self
+            .prompt(|_, _| "Open: ".to_string(), |_, _, _| {})
+            .unwrap_or(None)
+            .filter(|name| !name.is_empty());
+        let Some(file_name) = file_name else {
+            self.status_message = StatusMessage::from("Open aborted.".to_string());
+            return;
+        };
+        if let Some(index) = self
+            .buffers
+            .iter()
+            .position(|buffer| buffer.document.file_name.as_ref() == Some(&file_name))
+        {
+            self.switch_buffer(index);
+            return;
+        }
+        let Ok(document) = Document::open(&file_name) else {
+            self.status_message = StatusMessage::from(format!("ERR: Could not open file: {}", file_name));
+            return;
+        };
+        if document.is_read_only() {
+            self.status_message = StatusMessage::from(format!("WARNING: {} looks binary, opened read-only.", file_name));
+        }
+        // An untouched empty buffer, like the one tte starts with, is replaced.
+        let current = self.document();
+        let shown = self
+            .windows
+            .iter()
+            .filter(|window| window.buffer == self.window().buffer)
+            .count();
+        if current.file_name.is_none() && current.is_empty() && !current.is_dirty() && shown == 1 {
+            let buffer = self.window().buffer;
+            self.buffers[buffer] = Buffer::from(document);
+            let window = self.window_mut();
+            window.cursor_position = Position::default();
+            window.offset = Position::default();
+            window.selection_anchor = None;
         } else {
-            self.status_message = StatusMessage::from("Error writing file!".to_string());
+            self.buffers.push(Buffer::from(document));
+            self.switch_buffer(self.buffers.len() - 1);
+        }
+        self.check_swap(self.window().buffer);
+    }
+
+    /// Offers to recover buffer `index` from the swap file a previous
+    /// session left behind, to show how the two differ, or to discard it.
+    /// A swap file that is left alone may belong to another running tte, so
+    /// this buffer then keeps its hands off it.
+    fn check_swap(&mut self, index: usize) {
+        let buffer = &self.buffers[index];
+        let Some(file_name) = buffer.document.file_name.clone() else {
+            return;
+        };
+        if !buffer.foreign_swap {
+            return;
+        }
+        // A swap file that cannot be read is not ours to replace either.
+        let Ok(swapped) = swap::read(&file_name) else {
+            self.status_message = StatusMessage::from(format!(
+                "Could not read the swap file of {}; changes to it are not swapped.",
+                file_name
+            ));
+            return;
+        };
+        let question = format!(
+            "Found a swap file for {}: r = recover | d = diff | x = discard | ESC = leave it",
+            file_name
+        );
+        let text = self.buffers[index].document.text();
+        match self.ask_with_diff(index, &question, &['r', 'x'], "swap", &text, &swapped) {
+            Some('r') => {
+                self.buffers[index].document.replace_text(&swapped);
+                self.buffers[index].foreign_swap = false;
+                self.follow_edits(None);
+                self.status_message = StatusMessage::from(format!("Recovered unsaved changes to {}.", file_name));
+            },
+            Some(_) => {
+                swap::remove(&file_name);
+                self.buffers[index].foreign_swap = false;
+                self.status_message = StatusMessage::from(format!("Discarded the swap file of {}.", file_name));
+            },
+            None => {
+                self.status_message = StatusMessage::from(format!(
+                    "Left the swap file of {} alone; changes to it are not swapped.",
+                    file_name
+                ));
+            },
+        }
+    }
+
+    /// Shows buffer `index` and asks `question` until one of `choices` or
+    /// ESC is pressed, returning the choice or `None` for ESC. `d` opens a
+    /// read-only pane with the changes from `old` to `new`, which the arrow
+    /// and page keys then scroll. Once the question is answered, the pane
+    /// and its buffer are gone again and the window goes back to the buffer
+    /// it showed before.
+    fn ask_with_diff(
+        &mut self,
+        index: usize,
+        question: &str,
+        choices: &[char],
+        kind: &str,
+        old: &str,
+        new: &str,
+    ) -> Option<char> {
+        let previous = self.window().buffer;
+        if previous != index {
+            self.switch_buffer(index);
+        }
+        // The pane showing the diff and its buffer.
+        let mut diff: Option<(usize, usize)> = None;
+        let choice = loop {
+            self.status_message = StatusMessage::from(question.to_string());
+            if let Err(error) = self.refresh_screen() {
+                die(error);
+            }
+            match self.read_key() {
+                Ok(KeyEvent { code: KeyCode::Char(c), .. }) if choices.contains(&c) => break Some(c),
+                Ok(KeyEvent { code: KeyCode::Esc, .. }) => break None,
+                Ok(KeyEvent { code: KeyCode::Char('d'), .. }) if diff.is_none() => {
+                    let shown = self.active_window;
+                    self.split_window(SplitDirection::Vertical);
+                    if self.active_window == shown {
+                        continue;
+                    }
+                    let text = diff::diff(old, new).unwrap_or_else(|| "No differences.".to_string());
+                    let name = format!("{} ({} diff)", self.buffers[index].name(), kind);
+                    self.buffers.push(Buffer::from(Document::view(name, &text)));
+                    self.switch_buffer(self.buffers.len() - 1);
+                    self.window_mut().top_line = 0;
+                    diff = Some((self.active_window, self.buffers.len() - 1));
+                    self.active_window = shown;
+                },
+                Ok(KeyEvent { code: code @ (KeyCode::Up | KeyCode::Down | KeyCode::PageUp | KeyCode::PageDown), .. }) => {
+                    if let Some((window, _)) = diff {
+                        let lines = match code {
+                            KeyCode::Up | KeyCode::Down => 1,
+                            _ => self.windows[window].area.height.max(1),
+                        };
+                        self.scroll_window(window, matches!(code, KeyCode::Down | KeyCode::PageDown), lines);
+                    }
+                },
+                Ok(_) => (),
+                Err(error) => die(error),
+            }
+        };
+        if let Some((window, buffer)) = diff {
+            let shown = self.active_window;
+            self.active_window = window;
+            self.close_window();
+            self.active_window = if shown > window { shown - 1 } else { shown };
+            self.buffers.remove(buffer);
+            for window in &mut self.windows {
+                if window.buffer > buffer {
+                    window.buffer -= 1;
+                }
+            }
+        }
+        if self.window().buffer != previous {
+            self.switch_buffer(previous);
+        }
+        choice
+    }
+
+    /// Looks for files that other programs changed. Buffers without unsaved
+    /// changes simply follow the file; for the others the user decides.
+    fn check_files(&mut self) {
+        for index in 0..self.buffers.len() {
+            if !self.buffers[index].document.changed_on_disk() {
+                continue;
+            }
+            if self.buffers[index].document.is_dirty() {
+                self.resolve_conflict(index, false);
+            } else {
+                self.reload(index);
+            }
+        }
+    }
+
+    /// Asks what to do about buffer `index`, which has unsaved changes, now
+    /// that its file was changed by another program: take the file, write
+    /// the buffer over it, or carry on. When `saving`, carrying on means not
+    /// saving; otherwise the change is not brought up again.
+    fn resolve_conflict(&mut self, index: usize, saving: bool) {
+        let document = &self.buffers[index].document;
+        let Some(file_name) = document.file_name.clone() else {
+            return;
+        };
+        let on_disk = match Document::open(&file_name) {
+            Ok(on_disk) => on_disk.text(),
+            Err(error) => {
+                self.status_message = StatusMessage::from(format!("ERR: Could not read {}: {}", file_name, error));
+                return;
+            },
+        };
+        let question = format!(
+            "{} changed on disk: r = reload | o = overwrite | d = diff | ESC = {}",
+            file_name,
+            if saving { "cancel" } else { "keep editing" }
+        );
+        let text = document.text();
+        match self.ask_with_diff(index, &question, &['r', 'o'], "disk", &text, &on_disk) {
+            Some('r') => self.reload(index),
+            Some(_) => self.write_buffer(index),
+            None if saving => self.status_message = StatusMessage::from("Save aborted.".to_string()),
+            None => {
+                self.buffers[index].document.ignore_disk_changes();
+                self.status_message = StatusMessage::from(format!(
+                    "Kept your changes to {}; saving will overwrite the file.",
+                    file_name
+                ));
+            },
+        }
+    }
+
+    /// Brings buffer `index` up to date with its file as it is on disk now.
+    /// The reload can be undone like any other edit.
+    fn reload(&mut self, index: usize) {
+        let buffer = &mut self.buffers[index];
+        let Some(file_name) = buffer.document.file_name.clone() else {
+            return;
+        };
+        if let Err(error) = buffer.document.reload() {
+            self.status_message = StatusMessage::from(format!("ERR: Could not reload {}: {}", file_name, error));
+            return;
+        }
+        if !buffer.foreign_swap {
+            swap::remove(&file_name);
+        }
+        // No cursor was placed by the reload, so all of them follow it.
+        self.follow_edits(None);
+        self.scroll();
+        self.status_message = StatusMessage::from(format!("Reloaded {}, which changed on disk.", file_name));
+    }
+
+    /// Brings the swap files up to date: the text of each modified buffer is
+    /// written next to its file, and buffers without changes lose theirs.
+    fn write_swap_files(&mut self) {
+        self.swap_pending = false;
+        self.swap_written = Instant::now();
+        let mut failed = Vec::new();
+        for buffer in &self.buffers {
+            let Some(file_name) = &buffer.document.file_name else {
+                continue;
+            };
+            if buffer.foreign_swap || buffer.document.is_read_only() {
+                continue;
+            }
+            if !buffer.document.is_dirty() {
+                swap::remove(file_name);
+            } else if swap::write(file_name, &buffer.document.text()).is_err() {
+                failed.push(file_name.clone());
+            }
+        }
+        if !failed.is_empty() {
+            self.status_message = StatusMessage::from(format!("ERR: Could not write swap file for {}", failed.join(", ")));
+        }
+    }
+
+    fn remove_swap_files(&self) {
+        for buffer in &self.buffers {
+            if let (Some(file_name), false) = (&buffer.document.file_name, buffer.foreign_swap) {
+                swap::remove(file_name);
+            }
+        }
+    }
+
+    /// Moves the cursors, selections and views on every buffer past the
+    /// edits made to it since the last call. The cursor of window `except`,
+    /// if any, was already placed by the edit itself.
+    fn follow_edits(&mut self, except: Option<usize>) {
+        for (index, buffer) in self.buffers.iter_mut().enumerate() {
+            let changes = buffer.document.take_changes();
+            if changes.is_empty() {
+                continue;
+            }
+            for change in &changes {
+                buffer.cursor_position = change.shift(&buffer.cursor_position);
+                buffer.offset.y = change.shift(&Position { x: 0, y: buffer.offset.y }).y;
+            }
+            for (number, window) in self.windows.iter_mut().enumerate() {
+                if window.buffer != index || except == Some(number) {
+                    continue;
+                }
+                for change in &changes {
+                    window.cursor_position = change.shift(&window.cursor_position);
+                    window.selection_anchor = window.selection_anchor.map(|anchor| change.shift(&anchor));
+                    let top = change.shift(&Position { x: 0, y: window.offset.y }).y;
+                    if top != window.offset.y {
+                        window.offset.y = top;
+                        window.top_line = 0;
+                    }
+                }
+            }
+        }
+    }
+
+    fn switch_buffer(&mut self, index: usize) {
+        let window = &mut self.windows[self.active_window];
+        let buffer = &mut self.buffers[window.buffer];
+        buffer.cursor_position = window.cursor_position;
+        buffer.offset = window.offset;
+        let buffer = &self.buffers[index];
+        window.buffer = index;
+        window.cursor_position = buffer.cursor_position;
+        window.offset = buffer.offset;
+        window.selection_anchor = None;
+        self.status_message = StatusMessage::from(format!(
+            "Buffer {}/{}: {}",
+            index + 1,
+            self.buffers.len(),
+            buffer.name()
+        ));
+    }
+
+    fn split_window(&mut self, direction: SplitDirection) {
+        let window = self.window().clone();
+        if window.area.height < 3 || window.area.width < 3 {
+            self.status_message = StatusMessage::from("Not enough room to split.".to_string());
+            return;
+        }
+        self.windows.push(window);
+        let new_window = self.windows.len() - 1;
+        self.layout.split(self.active_window, new_window, direction);
+        self.active_window = new_window;
+        self.arrange_windows();
+        self.scroll();
+    }
+
+    fn close_window(&mut self) {
+        if self.windows.len() == 1 {
+            self.status_message = StatusMessage::from("Cannot close the last pane.".to_string());
+            return;
+        }
+        let closed = self.active_window;
+        let order = self.layout.windows();
+        let position = order.iter().position(|index| *index == closed).unwrap_or(0);
+        let next = order[if position > 0 { position - 1 } else { 1 }];
+        self.windows.remove(closed);
+        self.layout.remove(closed);
+        self.active_window = if next > closed { next - 1 } else { next };
+        self.arrange_windows();
+        self.scroll();
+    }
+
+    fn focus_next_window(&mut self) {
+        let order = self.layout.windows();
+        let position = order.iter().position(|index| *index == self.active_window).unwrap_or(0);
+        self.active_window = order[(position + 1) % order.len()];
+    }
+
+    fn list_buffers(&mut self) {
+        let list: Vec<String> = self
+            .buffers
+            .iter()
+            .enumerate()
+            .map(|(index, buffer)| {
+                let modified = if buffer.document.is_dirty() { "*" } else { "" };
+                let entry = format!("{}: {}{}", index + 1, buffer.name(), modified);
+                if index == self.window().buffer {
+                    format!("[{}]", entry)
+                } else {
+                    entry
+                }
+            })
+            .collect();
+        self.status_message = StatusMessage::from(format!("Buffers: {}", list.join(" | ")));
+    }
+
+    /// Waits for input in the main loop. While none comes, the swap files
+    /// are written and files changed by other programs are looked for.
+    fn wait_for_input(&mut self) -> Result<Event, Error> {
+        while !Terminal::poll(DISK_CHECK_INTERVAL)? {
+            if self.swap_pending {
+                self.write_swap_files();
+            }
+            self.check_files();
+            self.refresh_screen()?;
+        }
+        self.read_event()
+    }
+
+    /// Waits for the next event other than a resize. The screen is redrawn
+    /// for the new size whenever the terminal is resized in the meantime,
+    /// and the swap files are written when input pauses, or when it has not
+    /// paused for `SWAP_INTERVAL`.
+    fn read_event(&mut self) -> Result<Event, Error> {
+        loop {
+            if self.swap_pending && !Terminal::poll(SWAP_DELAY)? {
+                self.write_swap_files();
+                self.refresh_screen()?;
+                continue;
+            }
+            match Terminal::read_event()? {
+                Event::Resize(width, height) => {
+                    self.terminal.resize(width, height);
+                    self.scroll_windows();
+                    self.refresh_screen()?;
+                },
+                event => {
+                    if self.swap_pending && self.swap_written.elapsed() >= SWAP_INTERVAL {
+                        self.write_swap_files();
+                    }
+                    self.swap_pending = true;
+                    return Ok(event);
+                },
+            }
+        }
+    }
+
+    /// Waits for the next key press, ignoring the mouse.
+    fn read_key(&mut self) -> Result<KeyEvent, Error> {
+        loop {
+            if let Event::Key(key_event) = self.read_event()? {
+                return Ok(key_event);
+            }
         }
     }
 
     fn process_keypress(&mut self) -> Result<(), Error> {
-        let pressed_key = Terminal::read_key()?;
+        let pressed_key = match self.wait_for_input()? {
+            Event::Key(key_event) => key_event,
+            Event::Mouse(mouse_event) => {
+                self.process_mouse(mouse_event);
+                return Ok(());
+            },
+            Event::Paste(_) if self.document().is_read_only() => {
+                self.status_message = StatusMessage::from("This file is read-only.".to_string());
+                return Ok(());
+            },
+            Event::Paste(text) => {
+                // Terminals send line breaks in pastes as carriage returns.
+                self.insert_text(&text.replace("\r\n", "\n").replace('\r', "\n"));
+                self.scroll();
+                return Ok(());
+            },
+            _ => return Ok(()),
+        };
         match pressed_key {
             KeyEvent { modifiers: KeyModifiers::CONTROL, code: KeyCode::Char('q'), ..} => {
-                if self.quit_times > 0 && self.document.is_dirty() {
+                let dirty: Vec<String> = self
+                    .buffers
+                    .iter()
+                    .filter(|buffer| buffer.document.is_dirty())
+                    .map(Buffer::name)
+                    .collect();
+                if self.quit_times > 0 && !dirty.is_empty() {
                     self.status_message = StatusMessage::from(format!(
-                        "WARNING! File has unsaved changes. Press Ctrl-Q {} more times to quit.", 
+                        "WARNING! Unsaved changes in {}. Press Ctrl-Q {} more times to quit.", 
+                        dirty.join(", "),
                         self.quit_times
                     ));
                     self.quit_times -= 1;
                     return Ok(());
                 }
+                self.remove_swap_files();
                 self.should_quit = true;
             },
+            KeyEvent { modifiers: KeyModifiers::CONTROL, code: KeyCode::Char('r' | 'x' | 'v' | 'z' | 'y'), .. }
+            | KeyEvent { modifiers: KeyModifiers::ALT, code: KeyCode::Char('e' | 'n'), .. }
+            | KeyEvent {
+                modifiers: KeyModifiers::NONE | KeyModifiers::SHIFT,
+                code: KeyCode::Enter | KeyCode::Char(_) | KeyCode::Delete | KeyCode::Backspace,
+                ..
+            }
+                if self.document().is_read_only() =>
+            {
+                self.status_message = StatusMessage::from("This file is read-only.".to_string());
+            },
             KeyEvent { modifiers: KeyModifiers::CONTROL, code: KeyCode::Char('s'), .. } => self.save(),
+            KeyEvent { modifiers: KeyModifiers::CONTROL, code: KeyCode::Char('o'), .. } => self.open(),
+            KeyEvent { modifiers: KeyModifiers::CONTROL, code: KeyCode::Char('n'), .. } => {
+                self.switch_buffer((self.window().buffer + 1) % self.buffers.len());
+            },
+            KeyEvent { modifiers: KeyModifiers::CONTROL, code: KeyCode::Char('p'), .. } => {
+                let len = self.buffers.len();
+                self.switch_buffer((self.window().buffer + len - 1) % len);
+            },
+            KeyEvent { modifiers: KeyModifiers::CONTROL, code: KeyCode::Char('b'), .. } => self.list_buffers(),
+            KeyEvent { modifiers: KeyModifiers::ALT, code: KeyCode::Char('s'), .. } => {
+                self.split_window(SplitDirection::Horizontal);
+            },
+            KeyEvent { modifiers: KeyModifiers::ALT, code: KeyCode::Char('v'), .. } => {
+                self.split_window(SplitDirection::Vertical);
+            },
+            KeyEvent { modifiers: KeyModifiers::ALT, code: KeyCode::Char('q'), .. } => self.close_window(),
+            KeyEvent { modifiers: KeyModifiers::ALT, code: KeyCode::Char('w'), .. } => self.focus_next_window(),
+            KeyEvent { modifiers: KeyModifiers::ALT, code: KeyCode::Char('e'), .. } => {
+                let mut format = self.document().format();
+                format.line_ending = match format.line_ending {
+                    LineEnding::Lf => LineEnding::Crlf,
+                    LineEnding::Crlf => LineEnding::Lf,
+                };
+                self.document_mut().set_format(format);
+                self.status_message = StatusMessage::from(format!("Line endings set to {}.", format.line_ending.name()));
+            },
+            KeyEvent { modifiers: KeyModifiers::ALT, code: KeyCode::Char('n'), .. } => {
+                let mut format = self.document().format();
+                format.final_newline = !format.final_newline;
+                self.document_mut().set_format(format);
+                let message = if format.final_newline {
+                    "The file will end with a newline."
+                } else {
+                    "The file will not end with a newline."
+                };
+                self.status_message = StatusMessage::from(message.to_string());
+            },
+            KeyEvent { modifiers: KeyModifiers::ALT, code: KeyCode::Char('z'), .. } => {
+                self.config.wrap = !self.config.wrap;
+            },
+            KeyEvent { modifiers: KeyModifiers::ALT, code: KeyCode::Char('l'), .. } => {
+                self.config.line_numbers = self.config.line_numbers.next();
+                self.arrange_windows();
+            },
             KeyEvent { modifiers: KeyModifiers::CONTROL, code: KeyCode::Char('f'), .. } => self.search(),
+            KeyEvent { modifiers: KeyModifiers::CONTROL, code: KeyCode::Char('r'), .. } => self.replace(),
+            KeyEvent { modifiers: KeyModifiers::CONTROL, code: KeyCode::Char('z'), .. } => {
+                self.window_mut().selection_anchor = None;
+                if let Some(position) = self.document_mut().undo() {
+                    self.window_mut().cursor_position = position;
+                } else {
+                    self.status_message = StatusMessage::from("Nothing to undo.".to_string());
+                }
+            },
+            KeyEvent { modifiers: KeyModifiers::CONTROL, code: KeyCode::Char('y'), .. } => {
+                self.window_mut().selection_anchor = None;
+                if let Some(position) = self.document_mut().redo() {
+                    self.window_mut().cursor_position = position;
+                } else {
+                    self.status_message = StatusMessage::from("Nothing to redo.".to_string());
+                }
+            },
+            KeyEvent { modifiers: KeyModifiers::CONTROL, code: KeyCode::Char('c'), .. } => self.copy(),
+            KeyEvent { modifiers: KeyModifiers::CONTROL, code: KeyCode::Char('x'), .. } => {
+                self.copy();
+                self.delete_selection();
+            },
+            KeyEvent { modifiers: KeyModifiers::CONTROL, code: KeyCode::Char('v'), .. } => self.paste(),
             KeyEvent { code: KeyCode::Enter, .. } => {
-                self.document.insert(&self.cursor_position, '\n');
-                self.cursor_position = Position{ x: 0, y: self.cursor_position.y + 1 };
+                self.insert('\n');
+                self.window_mut().cursor_position = Position{ x: 0, y: self.window().cursor_position.y + 1 };
             },
             KeyEvent { code: KeyCode::Char(c), .. } => {
-                self.document.insert(&self.cursor_position, c);
+                self.insert(c);
                 self.move_cursor(KeyCode::Right);
             },
-            KeyEvent { code: KeyCode::Delete, .. } => self.document.delete(&self.cursor_position),
-            KeyEvent { code: KeyCode::Backspace, .. } => {
-                if self.cursor_position.x > 0 || self.cursor_position.y > 0 {
-                    self.move_cursor(KeyCode::Left);
-                    self.document.delete(&self.cursor_position);
+            KeyEvent { code: KeyCode::Delete | KeyCode::Backspace, .. } if self.window().selection().is_some() => {
+                self.delete_selection();
+            },
+            KeyEvent { code: KeyCode::Delete, .. } => {
+                self.window_mut().selection_anchor = None;
+                let at = self.window().cursor_position;
+                self.document_mut().delete(&at);
+            },
+            KeyEvent { code: KeyCode::Backspace, .. }
+                if self.window().cursor_position.x > 0 || self.window().cursor_position.y > 0 => {
+                self.window_mut().selection_anchor = None;
+                self.move_cursor(KeyCode::Left);
+                let at = self.window().cursor_position;
+                self.document_mut().delete(&at);
+            },
+            KeyEvent { 
+                modifiers: KeyModifiers::SHIFT,
+                code: 
+                KeyCode::Up 
+                | KeyCode::Down 
+                | KeyCode::Right 
+                | KeyCode::Left 
+                | KeyCode::PageUp
+                | KeyCode::PageDown
+                | KeyCode::Home
+                | KeyCode::End, .. } => {
+                if self.window().selection_anchor.is_none() {
+                    self.window_mut().selection_anchor = Some(self.window().cursor_position);
                 }
+                self.move_cursor(pressed_key.code);
             },
             KeyEvent { 
                 code: 
@@ -177,7 +979,10 @@ impl Editor {
                 | KeyCode::PageUp
                 | KeyCode::PageDown
                 | KeyCode::Home
-                | KeyCode::End, .. } => self.move_cursor(pressed_key.code),
+                | KeyCode::End, .. } => {
+                self.window_mut().selection_anchor = None;
+                self.move_cursor(pressed_key.code);
+            },
             _ => (),
         }
         self.scroll();
@@ -188,46 +993,181 @@ impl Editor {
         Ok(())
     }
 
-    fn scroll(&mut self) {
-        let Position {x, y} = self.cursor_position;
-        let width = self.terminal.size().width as usize;
-        let height = self.terminal.size().height as usize;
-        let mut offset = &mut self.offset;
-        if y < offset.y {
-            offset.y = y;
-        } else if y >= offset.y.saturating_add(height) {
-            offset.y = y.saturating_sub(height).saturating_add(1);
+    /// Clicking moves the cursor and focuses the window clicked in, dragging
+    /// selects and the wheel scrolls the window under the mouse. The status
+    /// and message bars don't react to the mouse.
+    fn process_mouse(&mut self, event: MouseEvent) {
+        let (column, row) = (event.column as usize, event.row as usize);
+        let clicked = self.windows.iter().position(|window| {
+            let area = window.area;
+            (area.x..area.x + area.width).contains(&column) && (area.y..area.y + area.height).contains(&row)
+        });
+        match event.kind {
+            MouseEventKind::Down(MouseButton::Left) => {
+                let Some(index) = clicked else {
+                    return;
+                };
+                self.active_window = index;
+                let position = self.position_at(&self.windows[index], column, row);
+                let window = self.window_mut();
+                window.cursor_position = position;
+                window.selection_anchor = None;
+            },
+            MouseEventKind::Drag(MouseButton::Left) => {
+                let position = self.position_at(self.window(), column, row);
+                let window = self.window_mut();
+                if window.selection_anchor.is_none() {
+                    window.selection_anchor = Some(window.cursor_position);
+                }
+                window.cursor_position = position;
+                // Dragging past the top or bottom edge keeps selecting
+                // further up or down.
+                let area = window.area;
+                if row < area.y {
+                    self.move_cursor(KeyCode::Up);
+                } else if row >= area.y + area.height {
+                    self.move_cursor(KeyCode::Down);
+                }
+            },
+            MouseEventKind::ScrollUp | MouseEventKind::ScrollDown => {
+                if let Some(index) = clicked {
+                    self.scroll_window(index, event.kind == MouseEventKind::ScrollDown, WHEEL_LINES);
+                }
+                return;
+            },
+            _ => return,
         }
-        if x < offset.x { 
-            offset.x = x;
-        } else if x >= offset.x.saturating_add(width) {
-            offset.x = x.saturating_sub(width).saturating_add(1);
+        self.scroll();
+    }
+
+    /// Document position drawn at the screen cell `column`, `row`, which is
+    /// clamped to the text area of `window`.
+    fn position_at(&self, window: &Window, column: usize, row: usize) -> Position {
+        let document = &self.buffers[window.buffer].document;
+        let area = window.area;
+        let column = column.saturating_sub(area.x + window.gutter);
+        let mut line = window.top_line + row.clamp(area.y, area.y + area.height.saturating_sub(1)) - area.y;
+        let mut y = window.offset.y;
+        let mut lines = self.screen_lines(window, y);
+        while line >= lines.len() && y.saturating_add(1) < document.len() {
+            line -= lines.len();
+            y += 1;
+            lines = self.screen_lines(window, y);
         }
+        let line = line.min(lines.len() - 1);
+        let Some(text) = document.row(y) else {
+            return Position { x: 0, y: y.min(document.len()) };
+        };
+        let start = if self.config.wrap {
+            lines[line].start
+        } else {
+            window.offset.x
+        };
+        Position {
+            x: self.x_on_line(&text, &lines, line, start + column),
+            y,
+        }
+    }
+
+    /// Moves the view of window `index` by `lines` screen lines, leaving its
+    /// cursor where it is.
+    fn scroll_window(&mut self, index: usize, down: bool, lines: usize) {
+        let window = &self.windows[index];
+        let len = self.buffers[window.buffer].document.len();
+        let mut top = (window.offset.y, window.top_line);
+        for _ in 0..lines {
+            if down && top.1 + 1 < self.screen_lines(window, top.0).len() {
+                top.1 += 1;
+            } else if down && top.0.saturating_add(1) < len {
+                top = (top.0 + 1, 0);
+            } else if !down && top.1 > 0 {
+                top.1 -= 1;
+            } else if !down && top.0 > 0 {
+                top = (top.0 - 1, self.screen_lines(window, top.0 - 1).len() - 1);
+            } else {
+                break;
+            }
+        }
+        let window = &mut self.windows[index];
+        window.offset.y = top.0;
+        window.top_line = top.1;
+    }
+
+    /// Inserts a character at the cursor, replacing the selection if any.
+    fn insert(&mut self, c: char) {
+        let replacing = self.window().selection().is_some();
+        if replacing {
+            self.document_mut().begin_group();
+            self.delete_selection();
+        }
+        let at = self.window().cursor_position;
+        self.document_mut().insert(&at, c);
+        if replacing {
+            self.document_mut().end_group();
+        }
+        self.window_mut().selection_anchor = None;
+    }
+
+    fn copy(&mut self) {
+        if let Some((start, end)) = self.window().selection() {
+            self.clipboard = Some(self.document().text_range(&start, &end));
+        }
+    }
+
+    fn paste(&mut self) {
+        let Some(text) = self.clipboard.clone() else {
+            self.status_message = StatusMessage::from("Clipboard is empty.".to_string());
+            return;
+        };
+        self.insert_text(&text);
+    }
+
+    /// Inserts `text` at the cursor as a single undo step, replacing the
+    /// selection if any, and leaves the cursor after it.
+    fn insert_text(&mut self, text: &str) {
+        self.document_mut().begin_group();
+        self.delete_selection();
+        let at = self.window().cursor_position;
+        self.window_mut().cursor_position = self.document_mut().insert_str(&at, text);
+        self.document_mut().end_group();
+    }
+
+    fn delete_selection(&mut self) {
+        if let Some((start, end)) = self.window().selection() {
+            self.document_mut().delete_range(&start, &end);
+            self.window_mut().cursor_position = start;
+        }
+        self.window_mut().selection_anchor = None;
     }
 
     fn move_cursor(&mut self, key_code: KeyCode) {
-        let terminal_height = self.terminal.size().height as usize;
-        let Position {mut x, mut y} = self.cursor_position;
-        let height = self.document.len();
-        let mut width = if let Some(row) = self.document.row(y) {
+        let terminal_height = self.window().area.height;
+        let Position {mut x, mut y} = self.window().cursor_position;
+        let height = self.document().len();
+        let mut width = if let Some(row) = self.document().row(y) {
             row.len()
         } else {
             0
         };
 
         match key_code {
-            KeyCode::Up => y = y.saturating_sub(1),
-            KeyCode::Down => {
-                if y.saturating_add(1) < height {
-                    y = y.saturating_add(1)
-                }
+            KeyCode::Up | KeyCode::Down | KeyCode::PageUp | KeyCode::PageDown if self.config.wrap => {
+                let down = matches!(key_code, KeyCode::Down | KeyCode::PageDown);
+                let steps = if matches!(key_code, KeyCode::Up | KeyCode::Down) {
+                    1
+                } else {
+                    terminal_height
+                };
+                Position { x, y } = self.move_by_screen_lines(down, steps);
             },
+            KeyCode::Up => y = y.saturating_sub(1),
+            KeyCode::Down if y.saturating_add(1) < height => y = y.saturating_add(1),
             KeyCode::Left => {
                 if x > 0 {
                     x -= 1;
                 } else if y > 0 {
                     y -= 1;
-                    x = if let Some(row) = self.document.row(y) {
+                    x = if let Some(row) = self.document().row(y) {
                         row.len()
                     } else {
                         0
@@ -261,7 +1201,7 @@ impl Editor {
             _ => (),
         }
 
-        width = if let Some(row) = self.document.row(y) {
+        width = if let Some(row) = self.document().row(y) {
             row.len()
         } else {
             0
@@ -270,125 +1210,335 @@ impl Editor {
             x = width;
         }
 
-        self.cursor_position = Position { x, y };
+        self.window_mut().cursor_position = Position { x, y };
     }
 
-    fn draw_welcome_message(&self) {
+    /// Position `steps` screen lines above or below the cursor, keeping its
+    /// column within the line where possible.
+    fn move_by_screen_lines(&self, down: bool, steps: usize) -> Position {
+        let window = self.window();
+        let (mut line, column) = self.cursor_line();
+        let mut y = window.cursor_position.y;
+        let mut lines = self.screen_lines(window, y);
+        for _ in 0..steps {
+            if down && line + 1 < lines.len() {
+                line += 1;
+            } else if down && y.saturating_add(1) < self.document().len() {
+                y += 1;
+                lines = self.screen_lines(window, y);
+                line = 0;
+            } else if !down && line > 0 {
+                line -= 1;
+            } else if !down && y > 0 {
+                y -= 1;
+                lines = self.screen_lines(window, y);
+                line = lines.len() - 1;
+            } else {
+                break;
+            }
+        }
+        let Some(row) = self.document().row(y) else {
+            return Position { x: 0, y };
+        };
+        Position {
+            x: self.x_on_line(&row, &lines, line, lines[line].start + column),
+            y,
+        }
+    }
+
+    /// Index of the grapheme at `column` of `row`, kept on screen line `line`
+    /// of the row's `lines`.
+    fn x_on_line(&self, row: &Row, lines: &[Range<usize>], line: usize, column: usize) -> usize {
+        let tab_width = self.config.tab_width;
+        let x = row.x_at(column, tab_width);
+        match lines.get(line + 1) {
+            Some(next) => x.min(row.x_at(next.start, tab_width).saturating_sub(1)),
+            None => x,
+        }
+    }
+
+    fn draw_welcome_message(&self, width: usize) -> String {
         let mut welcome_message = format!("TTE -- version {}", VERSION);
-        let width = self.terminal.size().width as usize;
         let len = welcome_message.len();
         let padding = width.saturating_sub(len) / 2;
         let spaces = " ".repeat(padding);
         welcome_message = format!("~{}{}", spaces, welcome_message);
-        welcome_message.truncate(width);
-        println!("{}\r", welcome_message);
-    }
-
-    pub fn draw_row(&self, row: &Row) {
-        let width = self.terminal.size().width as usize;
-        let start = self.offset.x;
-        let end = self.offset.x.saturating_add(width);
-        let row = row.render(start, end);
-        println!("{}\r", row);
-    }
-
-    fn draw_rows(&self) {
-        let height = self.terminal.size().height;
-        for terminal_row in 0..height {
-            Terminal::clear_current_line();
-            if let Some(row) = self.document.row(self.offset.y.saturating_add(terminal_row as usize)) {
-                self.draw_row(row);
-            } else if self.document.is_empty() && terminal_row == height / 3 {
-                self.draw_welcome_message();
+        truncate_to_width(&mut welcome_message, width);
+        welcome_message
+    }
+
+    /// Draws the part of `row` within `columns`, padded to the window width.
+    pub fn draw_row(&self, frame: &mut Frame, window: &Window, row: &Row, y: usize, columns: Range<usize>) {
+        let width = window.text_width();
+        let Range { start, end } = columns;
+        let selection = window.selection().and_then(|(from, to)| {
+            if y < from.y || y > to.y {
+                return None;
+            }
+            let from_x = if y == from.y { from.x } else { 0 };
+            let to_x = if y == to.y { to.x } else { row.len() };
+            Some((from_x, to_x))
+        });
+        let tab_width = self.config.tab_width;
+        let visible = row
+            .render_x(row.len(), tab_width)
+            .min(end)
+            .saturating_sub(start);
+        row.render(frame, start, end, selection, tab_width);
+        frame.print(&" ".repeat(width.saturating_sub(visible)));
+    }
+
+    fn draw_window(&self, frame: &mut Frame, index: usize) {
+        let window = &self.windows[index];
+        let document = &self.buffers[window.buffer].document;
+        let Rect { x, y: top, height, .. } = window.area;
+        let width = window.text_width();
+        let mut terminal_row = 0;
+        let mut y = window.offset.y;
+        let mut skip = window.top_line;
+        while terminal_row < height {
+            let Some(row) = document.row(y) else {
+                frame.move_to(&Position { x, y: top + terminal_row });
+                self.draw_line_number(frame, window, None);
+                let line = if document.is_empty() && self.windows.len() == 1 && terminal_row == height / 3 {
+                    self.draw_welcome_message(width)
+                } else {
+                    "~".to_string()
+                };
+                frame.print(&line);
+                frame.print(&" ".repeat(width.saturating_sub(line.width())));
+                terminal_row += 1;
+                y += 1;
+                continue;
+            };
+            let lines = if self.config.wrap {
+                row.wrap(width, self.config.tab_width)
             } else {
-                println!("~\r");
+                iter::once(window.offset.x..window.offset.x.saturating_add(width)).collect()
+            };
+            for (line, columns) in lines.into_iter().enumerate().skip(skip) {
+                if terminal_row == height {
+                    break;
+                }
+                frame.move_to(&Position { x, y: top + terminal_row });
+                self.draw_line_number(frame, window, (line == 0).then_some(y));
+                let end = columns.end.min(columns.start.saturating_add(width));
+                self.draw_row(frame, window, &row, y, columns.start..end);
+                terminal_row += 1;
+            }
+            skip = 0;
+            y += 1;
+        }
+        frame.move_to(&Position { x, y: top + height });
+        self.draw_status_bar(frame, index);
+    }
+
+    /// Prints the gutter for row `y`. It is left blank past the end of the
+    /// document and on the continuation lines of a wrapped row.
+    fn draw_line_number(&self, frame: &mut Frame, window: &Window, y: Option<usize>) {
+        if window.gutter == 0 {
+            return;
+        }
+        let digits = window.gutter - 1;
+        let Some(y) = y else {
+            frame.print(&" ".repeat(window.gutter));
+            return;
+        };
+        let cursor_y = window.cursor_position.y;
+        let number = match self.config.line_numbers {
+            LineNumbers::Relative if y != cursor_y => y.abs_diff(cursor_y),
+            _ => y.saturating_add(1),
+        };
+        frame.set_fg_color(LINE_NUMBER_FG_COLOR);
+        frame.print(&format!("{:>digits$} ", number));
+        frame.reset_fg_color();
+    }
+
+    fn draw_separators(&self, frame: &mut Frame) {
+        let size = self.terminal.size();
+        let area = Rect {
+            x: 0,
+            y: 0,
+            width: size.width as usize,
+            height: (size.height as usize).saturating_add(1),
+        };
+        let mut separators = Vec::new();
+        self.layout.arrange(area, &mut Vec::new(), &mut separators);
+        frame.set_bg_color(STATUS_BG_COLOR);
+        frame.set_fg_color(STATUS_FG_COLOR);
+        for separator in separators {
+            for y in separator.y..separator.y + separator.height {
+                frame.move_to(&Position { x: separator.x, y });
+                frame.print("|");
             }
         }
+        frame.reset_fg_color();
+        frame.reset_bg_color();
     }
 
-    fn draw_status_bar(&self) {
-        let width = self.terminal.size().width as usize;
-        let modified_indicator = if self.document.is_dirty() {
+    fn draw_status_bar(&self, frame: &mut Frame, index: usize) {
+        let window = &self.windows[index];
+        let buffer = &self.buffers[window.buffer];
+        let document = &buffer.document;
+        let width = window.area.width;
+        let modified_indicator = if document.is_read_only() {
+            " (read-only)"
+        } else if document.is_dirty() {
             " (modified)"
         } else {
             ""
         };
         let mut file_name = "[NO NAME]".to_string();
-        if let Some(name) = &self.document.file_name {
+        if let Some(name) = &document.file_name {
             file_name = name.clone();
-            file_name.truncate(20);
+            truncate_to_width(&mut file_name, 20);
+        }
+        if self.buffers.len() > 1 {
+            file_name = format!("[{}/{}] {}", window.buffer + 1, self.buffers.len(), file_name);
         }
 
         let mut status = format!(
             "{} - {} lines{}", 
             file_name, 
-            self.document.len(),
+            document.len(),
             modified_indicator
         );
         let line_indicator = format!(
-            "{} | {}/{}",
-            self.document.file_type(),
-            self.cursor_position.y.saturating_add(1),
-            self.document.len()
+            "{} | {} | {}/{}",
+            document.file_type(),
+            document.format().describe(),
+            window.cursor_position.y.saturating_add(1),
+            document.len()
         );
-        let len = status.len() + line_indicator.len();
+        let len = status.width() + line_indicator.width();
         status.push_str(&" ".repeat(width.saturating_sub(len)));
         status = format!("{}{}", status, line_indicator);
-        status.truncate(width);
-        Terminal::set_bg_color(STATUS_BG_COLOR);
-        Terminal::set_fg_color(STATUS_FG_COLOR);
-        println!("{}\r", status);
-        Terminal::reset_fg_color();
-        Terminal::reset_bg_color();
+        truncate_to_width(&mut status, width);
+        if index == self.active_window {
+            frame.set_bg_color(STATUS_BG_COLOR);
+            frame.set_fg_color(STATUS_FG_COLOR);
+        } else {
+            frame.set_bg_color(INACTIVE_STATUS_BG_COLOR);
+            frame.set_fg_color(STATUS_FG_COLOR);
+        }
+        frame.print(&status);
+        frame.reset_fg_color();
+        frame.reset_bg_color();
     }
 
-    fn draw_message_bar(&self) {
-        Terminal::clear_current_line();
+    fn draw_message_bar(&self, frame: &mut Frame) {
+        frame.move_to(&Position {
+            x: 0,
+            y: (self.terminal.size().height as usize).saturating_add(1),
+        });
         let message = &self.status_message;
         if Instant::now() - message.time < Duration::new(5, 0) {
-            let mut text = message.text.clone();
-            text.truncate(self.terminal.size().width as usize);
-            print!("{}", text);
+            frame.print(&message.text);
         }
     }
 
-    fn prompt<C>(&mut self, prompt: &str, mut callback: C) -> Result<Option<String>, Error> 
+    /// Asks for a line of input in the message bar. Returns `None` when the
+    /// prompt is cancelled with ESC; an empty answer is `Some("")`.
+    fn prompt<P, C>(&mut self, prompt: P, mut callback: C) -> Result<Option<String>, Error> 
     where
+        P: Fn(&Self, &str) -> String,
         C: FnMut(&mut Self, KeyEvent, &String)
     {
         let mut result = String::new();
         loop {
-            self.status_message = StatusMessage::from(format!("{}{}", prompt, result));
+            self.status_message = StatusMessage::from(format!("{}{}", prompt(self, &result), result));
             self.refresh_screen()?;
-            let key_event = Terminal::read_key()?;
+            let key_event = match self.read_event()? {
+                Event::Key(key_event) => key_event,
+                Event::Paste(text) => {
+                    // Answers are a single line, so line breaks are dropped.
+                    result.extend(text.chars().filter(|c| !matches!(c, '\n' | '\r')));
+                    // Let the callback see the change as a key without a meaning of its own.
+                    KeyEvent::new(KeyCode::Null, KeyModifiers::NONE)
+                },
+                _ => continue,
+            };
             match key_event {
                 KeyEvent { modifiers: KeyModifiers::CONTROL, .. } => continue,
+                KeyEvent { modifiers: KeyModifiers::ALT, .. } => {},
                 KeyEvent { code: KeyCode::Enter, .. } => break,
                 KeyEvent { code: KeyCode::Esc, .. } => {
-                    result.truncate(0);
-                    break;
+                    self.status_message = StatusMessage::from(String::new());
+                    return Ok(None);
+                },
+                KeyEvent { code: KeyCode::Backspace, .. } => {
+                    result.pop();
                 },
-                KeyEvent { code: KeyCode::Backspace, .. } => result.truncate(result.len().saturating_sub(1)),
                 KeyEvent { code: KeyCode::Char(c), .. } => result.push(c),
                 _ => {}
             }
             callback(self, key_event, &result);
         }
         self.status_message = StatusMessage::from(String::new());
-        if result.is_empty() {
-            return Ok(None);
-        }
         Ok(Some(result))
     }
 
+    fn toggle_search_option(&mut self, c: char) {
+        let options = &mut self.search_options;
+        match c {
+            'r' => options.regex = !options.regex,
+            'c' => options.ignore_case = !options.ignore_case,
+            'w' => options.whole_word = !options.whole_word,
+            _ => (),
+        }
+    }
+
+    fn pattern_prompt(&self, title: &str, query: &str, status: &str, hint: &str) -> String {
+        let options = self.search_options.describe();
+        let mode = if options.is_empty() {
+            String::new()
+        } else {
+            format!(" [{}]", options)
+        };
+        let error = if Query::new(query, self.search_options).is_err() {
+            " (invalid pattern)"
+        } else {
+            ""
+        };
+        format!("{}{}{}{} ({}): ", title, mode, error, status, hint)
+    }
+
+    fn search_status(&self) -> String {
+        let Some(query) = &self.highlighted_word else {
+            return String::new();
+        };
+        let (current, total) = self.document().count_matches(query, &self.window().cursor_position);
+        let mut status = match current {
+            _ if total == 0 => "no matches".to_string(),
+            Some(current) => format!("match {} of {}", current, total),
+            None => format!("{} matches", total),
+        };
+        if self.search_wrapped {
+            status.push_str(", search wrapped");
+        }
+        format!(" ({})", status)
+    }
+
     fn search(&mut self) {
-        let old_position = self.cursor_position.clone();
+        self.window_mut().selection_anchor = None;
+        self.search_wrapped = false;
+        let old_position = self.window().cursor_position;
         let mut direction = SearchDirection::Forward;
         let query = self
-            .prompt("Search (ESC to canel, Arrows to navigate): ", 
+            .prompt(|editor, query| editor.pattern_prompt(
+                "Search",
+                query,
+                &editor.search_status(),
+                "ESC to cancel, Arrows to navigate, Alt-R/C/W = regex/case/word",
+            ),
             |editor, key_event, query| {
                 let mut moved = false;
+                editor.search_wrapped = false;
                 match key_event {
+                    KeyEvent { modifiers: KeyModifiers::ALT, code: KeyCode::Char(c), .. } => {
+                        editor.toggle_search_option(c);
+                        direction = SearchDirection::Forward;
+                    },
                     KeyEvent { code: KeyCode::Right | KeyCode::Down, .. } => {
                         direction = SearchDirection::Forward;
                         editor.move_cursor(KeyCode::Right);
@@ -400,28 +1550,124 @@ impl Editor {
                     _ => direction = SearchDirection::Forward,
                 }
 
-                if let Some(position) = 
+                let Ok(query) = Query::new(query, editor.search_options) else {
+                    if moved {
+                        editor.move_cursor(KeyCode::Left);
+                    }
+                    editor.highlighted_word = None;
+                    return;
+                };
+                if let Some((position, wrapped)) = 
                     editor
-                        .document
-                        .find(&query, &editor.cursor_position, direction)
+                        .document()
+                        .find(&query, &editor.window().cursor_position, direction)
                 {
-                    editor.cursor_position = position;
+                    editor.window_mut().cursor_position = position;
+                    editor.search_wrapped = wrapped;
                     editor.scroll();
                 } else if moved {
                     editor.move_cursor(KeyCode::Left);
                 } 
-                editor.highlighted_word = Some(query.to_string());
+                editor.highlighted_word = Some(query);
             })
-            .unwrap_or(None);
+            .unwrap_or(None)
+            .filter(|query| !query.is_empty());
         if query.is_none() {
-            self.cursor_position = old_position;
+            self.window_mut().cursor_position = old_position;
             self.scroll();
         }
         self.highlighted_word = None;
     }
+
+    fn replace(&mut self) {
+        self.window_mut().selection_anchor = None;
+        let pattern = self
+            .prompt(
+                |editor, query| editor.pattern_prompt(
+                    "Replace",
+                    query,
+                    "",
+                    "ESC to cancel, Alt-R/C/W = regex/case/word",
+                ),
+                |editor, key_event, _| {
+                    if let KeyEvent { modifiers: KeyModifiers::ALT, code: KeyCode::Char(c), .. } = key_event {
+                        editor.toggle_search_option(c);
+                    }
+                },
+            )
+            .unwrap_or(None)
+            .filter(|pattern| !pattern.is_empty());
+        let Some(pattern) = pattern else {
+            self.status_message = StatusMessage::from("Replace aborted.".to_string());
+            return;
+        };
+        let query = match Query::new(&pattern, self.search_options) {
+            Ok(query) => query,
+            Err(_) => {
+                self.status_message = StatusMessage::from(format!("Invalid pattern: {}", pattern));
+                return;
+            },
+        };
+        let prompt = format!("Replace {} with: ", pattern);
+        // An empty replacement deletes the matches; only ESC cancels.
+        let Ok(Some(replacement)) = self.prompt(|_, _| prompt.clone(), |_, _, _| {}) else {
+            self.status_message = StatusMessage::from("Replace aborted.".to_string());
+            return;
+        };
+
+        let mut replaced = 0;
+        let mut replace_all = false;
+        let mut replacer = Replacer::new(self.window().cursor_position);
+        self.document_mut().begin_group();
+        while let Some((start, end)) = replacer.next(self.document(), &query) {
+            self.window_mut().cursor_position = end;
+            self.window_mut().selection_anchor = Some(start);
+            self.scroll();
+            if !replace_all {
+                self.status_message = StatusMessage::from(
+                    "Replace? y = yes | n = skip | a = all | q = quit".to_string(),
+                );
+                if let Err(error) = self.refresh_screen() {
+                    die(error);
+                }
+                match self.read_key() {
+                    Ok(KeyEvent { code: KeyCode::Char('y'), .. }) => (),
+                    Ok(KeyEvent { code: KeyCode::Char('a'), .. }) => replace_all = true,
+                    Ok(KeyEvent { code: KeyCode::Char('n'), .. }) => {
+                        replacer.skip(end);
+                        continue;
+                    },
+                    Ok(KeyEvent { code: KeyCode::Char('q') | KeyCode::Esc, .. }) => break,
+                    Ok(_) => continue,
+                    Err(error) => die(error),
+                }
+            }
+            let buffer = self.window().buffer;
+            let at = replacer.replace(&mut self.buffers[buffer].document, &query, (start, end), &replacement);
+            self.window_mut().cursor_position = at;
+            replaced += 1;
+        }
+        self.document_mut().end_group();
+        self.window_mut().selection_anchor = None;
+        self.scroll();
+        self.status_message = StatusMessage::from(format!("Replaced {} occurrence(s).", replaced));
+    }
+}
+
+/// Cuts `text` down to at most `width` screen columns.
+fn truncate_to_width(text: &mut String, width: usize) {
+    let mut used = 0;
+    for (index, c) in text.char_indices() {
+        used += c.width().unwrap_or(0);
+        if used > width {
+            text.truncate(index);
+            return;
+        }
+    }
 }
 
 fn die(e: Error) {
     Terminal::clear_screen();
-    panic!("{:?}", e);
+    Terminal::cursor_position(&Position::default());
+    panic!("{}", e);
 }
\ No newline at end of file
@@ -0,0 +1,259 @@
+use std::io::{Error, ErrorKind};
+use std::str;
+
+#[derive(PartialEq, Clone, Copy)]
+pub enum Encoding {
+    Utf8,
+    /// UTF-8 starting with a byte order mark.
+    Utf8Bom,
+    /// UTF-16, with `bom` set when the file starts with a byte order mark.
+    Utf16Le { bom: bool },
+    Utf16Be { bom: bool },
+    Latin1,
+}
+
+impl Encoding {
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Utf8 => "UTF-8",
+            Encoding::Utf8Bom => "UTF-8 BOM",
+            Encoding::Utf16Le { .. } => "UTF-16LE",
+            Encoding::Utf16Be { .. } => "UTF-16BE",
+            Encoding::Latin1 => "Latin-1",
+        }
+    }
+
+    /// Guesses the encoding of `bytes` and decodes them. The flag is set
+    /// when the bytes look like a binary file rather than text; those are
+    /// decoded as Latin-1, which keeps every byte.
+    pub fn decode(mut bytes: Vec<u8>) -> (Self, String, bool) {
+        if bytes.starts_with(b"\xEF\xBB\xBF") {
+            bytes.drain(..3);
+            match String::from_utf8(bytes) {
+                Ok(text) => return (Encoding::Utf8Bom, text, false),
+                Err(error) => {
+                    bytes = error.into_bytes();
+                    bytes.splice(0..0, *b"\xEF\xBB\xBF");
+                },
+            }
+        }
+        if let Some(rest) = bytes.strip_prefix(b"\xFF\xFE") {
+            if let Some(text) = decode_utf16(rest, u16::from_le_bytes) {
+                return (Encoding::Utf16Le { bom: true }, text, false);
+            }
+        }
+        if let Some(rest) = bytes.strip_prefix(b"\xFE\xFF") {
+            if let Some(text) = decode_utf16(rest, u16::from_be_bytes) {
+                return (Encoding::Utf16Be { bom: true }, text, false);
+            }
+        }
+        // Valid UTF-8 is taken over as it is, without a copy.
+        let bytes = match String::from_utf8(bytes) {
+            Ok(text) if !looks_binary(&text) => return (Encoding::Utf8, text, false),
+            Ok(text) => text.into_bytes(),
+            Err(error) => error.into_bytes(),
+        };
+        let bytes = &bytes[..];
+        // Without a byte order mark, UTF-16 shows itself by the zero bytes
+        // in the high half of mostly ASCII text.
+        let (even_zeros, odd_zeros) = bytes.iter().enumerate().fold((0, 0), |(even, odd), (index, byte)| {
+            match (*byte, index % 2) {
+                (0, 0) => (even + 1, odd),
+                (0, _) => (even, odd + 1),
+                _ => (even, odd),
+            }
+        });
+        let half = bytes.len() / 2;
+        let guess = if odd_zeros * 2 > half && even_zeros == 0 {
+            Some((Encoding::Utf16Le { bom: false }, decode_utf16(bytes, u16::from_le_bytes)))
+        } else if even_zeros * 2 > half && odd_zeros == 0 {
+            Some((Encoding::Utf16Be { bom: false }, decode_utf16(bytes, u16::from_be_bytes)))
+        } else {
+            None
+        };
+        if let Some((encoding, Some(text))) = guess {
+            if !looks_binary(&text) {
+                return (encoding, text, false);
+            }
+        }
+        let text: String = bytes.iter().map(|&byte| char::from(byte)).collect();
+        let binary = looks_binary(&text);
+        (Encoding::Latin1, text, binary)
+    }
+
+    pub fn encode(self, text: &str) -> Result<Vec<u8>, Error> {
+        let bytes = match self {
+            Encoding::Utf8 => text.as_bytes().to_vec(),
+            Encoding::Utf8Bom => [b"\xEF\xBB\xBF", text.as_bytes()].concat(),
+            Encoding::Utf16Le { bom } => bom
+                .then_some(0xFEFF)
+                .into_iter()
+                .chain(text.encode_utf16())
+                .flat_map(u16::to_le_bytes)
+                .collect(),
+            Encoding::Utf16Be { bom } => bom
+                .then_some(0xFEFF)
+                .into_iter()
+                .chain(text.encode_utf16())
+                .flat_map(u16::to_be_bytes)
+                .collect(),
+            Encoding::Latin1 => text
+                .chars()
+                .map(|c| {
+                    u8::try_from(c).map_err(|_| {
+                        Error::new(ErrorKind::InvalidData, format!("'{}' cannot be saved as Latin-1", c))
+                    })
+                })
+                .collect::<Result<_, _>>()?,
+        };
+        Ok(bytes)
+    }
+}
+
+fn decode_utf16(bytes: &[u8], from_bytes: fn([u8; 2]) -> u16) -> Option<String> {
+    let pairs = bytes.chunks_exact(2);
+    if !pairs.remainder().is_empty() {
+        return None;
+    }
+    let units = pairs.map(|pair| from_bytes([pair[0], pair[1]]));
+    char::decode_utf16(units).collect::<Result<_, _>>().ok()
+}
+
+/// Text with NUL characters, or where control characters other than
+/// whitespace make up more than a tenth, is taken for binary data.
+fn looks_binary(text: &str) -> bool {
+    let mut chars = 0;
+    let mut controls = 0;
+    for c in text.chars() {
+        if c == '\0' {
+            return true;
+        }
+        chars += 1;
+        if c.is_control() && !matches!(c, '\t' | '\n' | '\r' | '\x0c') {
+            controls += 1;
+        }
+    }
+    controls * 10 > chars
+}
+
+#[derive(PartialEq, Clone, Copy)]
+pub enum LineEnding {
+    Lf,
+    Crlf,
+}
+
+impl LineEnding {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::Crlf => "\r\n",
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "LF",
+            LineEnding::Crlf => "CRLF",
+        }
+    }
+}
+
+/// How a document's text is laid out in its file, so that saving writes it
+/// back the way it was found.
+#[derive(PartialEq, Clone, Copy)]
+pub struct FileFormat {
+    pub encoding: Encoding,
+    pub line_ending: LineEnding,
+    /// Whether the last line is followed by a line ending.
+    pub final_newline: bool,
+}
+
+impl Default for FileFormat {
+    fn default() -> Self {
+        Self {
+            encoding: Encoding::Utf8,
+            line_ending: LineEnding::Lf,
+            final_newline: true,
+        }
+    }
+}
+
+impl FileFormat {
+    /// Detects the format of `content`, which was decoded from `encoding`.
+    /// The first line break decides the line ending; files mixing both are
+    /// saved with that one throughout.
+    pub fn detect(content: &str, encoding: Encoding) -> Self {
+        let line_ending = match content.find('\n') {
+            Some(index) if content[..index].ends_with('\r') => LineEnding::Crlf,
+            _ => LineEnding::Lf,
+        };
+        Self {
+            encoding,
+            line_ending,
+            final_newline: content.is_empty() || content.ends_with('\n'),
+        }
+    }
+
+    /// Short description for the status bar, e.g. "CRLF" or
+    /// "Latin-1, LF, noeol". UTF-8 goes without saying.
+    pub fn describe(&self) -> String {
+        let mut parts = Vec::new();
+        if self.encoding != Encoding::Utf8 {
+            parts.push(self.encoding.name());
+        }
+        parts.push(self.line_ending.name());
+        if !self.final_newline {
+            parts.push("noeol");
+        }
+        parts.join(", ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Decodes `bytes`, expecting `encoding`, and checks that encoding the
+    /// text again gives back the same bytes.
+    fn round_trip(bytes: &[u8], encoding: Encoding, text: &str) {
+        let (decoded, content, binary) = Encoding::decode(bytes.to_vec());
+        assert!(decoded == encoding, "decoded as {}", decoded.name());
+        assert_eq!(content, text);
+        assert!(!binary);
+        assert_eq!(encoding.encode(&content).unwrap(), bytes);
+    }
+
+    fn utf16(text: &str, bom: bool, to_bytes: fn(u16) -> [u8; 2]) -> Vec<u8> {
+        bom.then_some(0xFEFF).into_iter().chain(text.encode_utf16()).flat_map(to_bytes).collect()
+    }
+
+    #[test]
+    fn utf8_round_trips() {
+        round_trip("héllo\n".as_bytes(), Encoding::Utf8, "héllo\n");
+        round_trip("\u{FEFF}héllo\n".as_bytes(), Encoding::Utf8Bom, "héllo\n");
+    }
+
+    #[test]
+    fn utf16_round_trips_with_and_without_bom() {
+        let text = "hello\nwörld\n";
+        round_trip(&utf16(text, true, u16::to_le_bytes), Encoding::Utf16Le { bom: true }, text);
+        round_trip(&utf16(text, false, u16::to_le_bytes), Encoding::Utf16Le { bom: false }, text);
+        round_trip(&utf16(text, true, u16::to_be_bytes), Encoding::Utf16Be { bom: true }, text);
+        round_trip(&utf16(text, false, u16::to_be_bytes), Encoding::Utf16Be { bom: false }, text);
+    }
+
+    #[test]
+    fn latin1_round_trips() {
+        round_trip(b"caf\xE9\n", Encoding::Latin1, "café\n");
+        assert!(Encoding::Latin1.encode("€").is_err());
+    }
+
+    #[test]
+    fn detects_binary_files() {
+        let (encoding, content, binary) = Encoding::decode(b"ab\0cd\xFF".to_vec());
+        assert!(binary);
+        assert!(encoding == Encoding::Latin1);
+        assert_eq!(Encoding::Latin1.encode(&content).unwrap(), b"ab\0cd\xFF");
+        assert!(!Encoding::decode(b"plain text\n".to_vec()).2);
+    }
+}
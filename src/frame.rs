@@ -0,0 +1,148 @@
+use crate::Position;
+use crossterm::style::Color;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+#[derive(Clone, Copy, PartialEq)]
+pub struct Style {
+    pub fg: Color,
+    pub bg: Color,
+    pub reverse: bool,
+}
+
+impl Default for Style {
+    fn default() -> Self {
+        Self {
+            fg: Color::Reset,
+            bg: Color::Reset,
+            reverse: false,
+        }
+    }
+}
+
+/// One terminal cell. The cell right of a wide grapheme has an empty symbol,
+/// since the terminal fills it when drawing the grapheme.
+#[derive(Clone, PartialEq)]
+pub struct Cell {
+    pub symbol: String,
+    pub style: Style,
+}
+
+impl Default for Cell {
+    fn default() -> Self {
+        Self {
+            symbol: " ".to_string(),
+            style: Style::default(),
+        }
+    }
+}
+
+/// An off-screen picture of the whole terminal. The editor draws a frame
+/// with the same calls it would use on the terminal, and `Terminal::draw`
+/// only sends the cells that differ from the previous frame.
+pub struct Frame {
+    width: usize,
+    height: usize,
+    cells: Vec<Cell>,
+    pen: Position,
+    style: Style,
+    cursor: Option<Position>,
+}
+
+impl Frame {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            cells: vec![Cell::default(); width * height],
+            pen: Position::default(),
+            style: Style::default(),
+            cursor: None,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn cell(&self, x: usize, y: usize) -> &Cell {
+        &self.cells[y * self.width + x]
+    }
+
+    /// Where the terminal cursor is shown, if it is shown at all.
+    pub fn cursor(&self) -> Option<Position> {
+        self.cursor
+    }
+
+    pub fn set_cursor(&mut self, position: Option<Position>) {
+        self.cursor = position;
+    }
+
+    pub fn move_to(&mut self, position: &Position) {
+        self.pen = *position;
+    }
+
+    pub fn set_fg_color(&mut self, color: Color) {
+        self.style.fg = color;
+    }
+
+    pub fn reset_fg_color(&mut self) {
+        self.style.fg = Color::Reset;
+    }
+
+    pub fn set_bg_color(&mut self, color: Color) {
+        self.style.bg = color;
+    }
+
+    pub fn reset_bg_color(&mut self) {
+        self.style.bg = Color::Reset;
+    }
+
+    pub fn set_reverse(&mut self, reverse: bool) {
+        self.style.reverse = reverse;
+    }
+
+    /// Writes `text` at the pen with the current style and moves the pen
+    /// past it. Whatever does not fit on the line is cut off.
+    pub fn print(&mut self, text: &str) {
+        let Position { mut x, y } = self.pen;
+        if y >= self.height {
+            return;
+        }
+        for grapheme in text.graphemes(true) {
+            let width = cell_width(grapheme);
+            if x + width > self.width {
+                // A wide grapheme cut by the edge leaves a blank behind.
+                if x < self.width {
+                    self.put(x, y, " ");
+                }
+                x = self.width;
+                break;
+            }
+            self.put(x, y, grapheme);
+            if width == 2 {
+                self.put(x + 1, y, "");
+            }
+            x += width;
+        }
+        self.pen.x = x;
+    }
+
+    fn put(&mut self, x: usize, y: usize, symbol: &str) {
+        let index = y * self.width + x;
+        self.cells[index] = Cell {
+            symbol: symbol.to_string(),
+            style: self.style,
+        };
+    }
+}
+
+/// Number of terminal cells a grapheme other than a tab takes: two for
+/// full-width characters and emoji, one for everything else.
+pub fn cell_width(grapheme: &str) -> usize {
+    grapheme.width().clamp(1, 2)
+}
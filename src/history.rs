@@ -0,0 +1,243 @@
+use crate::Position;
+use unicode_segmentation::UnicodeSegmentation;
+
+#[derive(Clone)]
+pub enum Operation {
+    Insert { at: Position, text: String },
+    Delete { at: Position, text: String },
+}
+
+struct Step {
+    id: usize,
+    operations: Vec<Operation>,
+    sealed: bool,
+}
+
+#[derive(Default)]
+pub struct History {
+    undo_stack: Vec<Step>,
+    redo_stack: Vec<Step>,
+    next_id: usize,
+    saved_id: Option<usize>,
+    group_depth: usize,
+}
+
+impl History {
+    pub fn record(&mut self, operation: Operation) {
+        self.redo_stack.clear();
+        if self.group_depth > 0 {
+            if let Some(step) = self.undo_stack.last_mut() {
+                if !step.sealed {
+                    step.operations.push(operation);
+                    return;
+                }
+            }
+        } else if let Some(step) = self.undo_stack.last_mut() {
+            if !step.sealed && Self::merge(step, &operation) {
+                return;
+            }
+        }
+        self.push_step(operation);
+    }
+
+    /// Merges a single character insert into the previous step when it
+    /// continues the text typed there, so a typed word is undone at once.
+    fn merge(step: &mut Step, operation: &Operation) -> bool {
+        let (Some(Operation::Insert { at: last_at, text: last_text }), Operation::Insert { at, text }) =
+            (step.operations.last_mut(), operation)
+        else {
+            return false;
+        };
+        if text.graphemes(true).count() != 1
+            || text.contains('\n')
+            || last_text.contains('\n')
+            || at.y != last_at.y
+        {
+            return false;
+        }
+        let last_len = last_text[..].graphemes(true).count();
+        if last_at.x.saturating_add(last_len) != at.x {
+            return false;
+        }
+        last_text.push_str(text);
+        true
+    }
+
+    fn push_step(&mut self, operation: Operation) {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.undo_stack.push(Step {
+            id,
+            operations: vec![operation],
+            sealed: false,
+        });
+    }
+
+    /// Starts a group: every operation recorded until the matching
+    /// `end_group` is undone and redone as a single step.
+    pub fn begin_group(&mut self) {
+        if self.group_depth == 0 {
+            self.seal();
+            self.redo_stack.clear();
+            let id = self.next_id;
+            self.next_id += 1;
+            self.undo_stack.push(Step {
+                id,
+                operations: Vec::new(),
+                sealed: false,
+            });
+        }
+        self.group_depth += 1;
+    }
+
+    pub fn end_group(&mut self) {
+        if self.group_depth == 0 {
+            return;
+        }
+        self.group_depth -= 1;
+        if self.group_depth == 0 {
+            if let Some(step) = self.undo_stack.last_mut() {
+                if step.operations.is_empty() {
+                    self.undo_stack.pop();
+                } else {
+                    step.sealed = true;
+                }
+            }
+        }
+    }
+
+    /// Stops the latest step from absorbing further typing.
+    pub fn seal(&mut self) {
+        if let Some(step) = self.undo_stack.last_mut() {
+            step.sealed = true;
+        }
+    }
+
+    pub fn mark_saved(&mut self) {
+        self.seal();
+        self.saved_id = self.current_id();
+    }
+
+    pub fn is_saved(&self) -> bool {
+        self.saved_id == self.current_id()
+    }
+
+    fn current_id(&self) -> Option<usize> {
+        self.undo_stack.last().map(|step| step.id)
+    }
+
+    pub fn undo(&mut self) -> Option<Vec<Operation>> {
+        let mut step = self.undo_stack.pop()?;
+        step.sealed = true;
+        let operations = step.operations.clone();
+        self.redo_stack.push(step);
+        Some(operations)
+    }
+
+    pub fn redo(&mut self) -> Option<Vec<Operation>> {
+        let step = self.redo_stack.pop()?;
+        let operations = step.operations.clone();
+        self.undo_stack.push(step);
+        Some(operations)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn insert(x: usize, text: &str) -> Operation {
+        Operation::Insert { at: Position { x, y: 0 }, text: text.to_string() }
+    }
+
+    fn texts(operations: &[Operation]) -> Vec<String> {
+        operations
+            .iter()
+            .map(|operation| match operation {
+                Operation::Insert { text, .. } => format!("+{}", text),
+                Operation::Delete { text, .. } => format!("-{}", text),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn merges_consecutive_typing() {
+        let mut history = History::default();
+        history.record(insert(0, "a"));
+        history.record(insert(1, "b"));
+        history.record(insert(2, "c"));
+        assert_eq!(texts(&history.undo().unwrap()), ["+abc"]);
+        assert!(history.undo().is_none());
+    }
+
+    #[test]
+    fn does_not_merge_apart_sealed_or_multiline_inserts() {
+        let mut history = History::default();
+        history.record(insert(0, "a"));
+        history.record(insert(5, "b"));
+        history.seal();
+        history.record(insert(6, "c"));
+        history.record(insert(7, "\n"));
+        assert_eq!(texts(&history.undo().unwrap()), ["+\n"]);
+        assert_eq!(texts(&history.undo().unwrap()), ["+c"]);
+        assert_eq!(texts(&history.undo().unwrap()), ["+b"]);
+        assert_eq!(texts(&history.undo().unwrap()), ["+a"]);
+    }
+
+    #[test]
+    fn groups_undo_and_redo_as_one_step() {
+        let mut history = History::default();
+        history.record(insert(0, "a"));
+        history.begin_group();
+        history.record(Operation::Delete { at: Position::default(), text: "a".to_string() });
+        history.begin_group();
+        history.record(insert(0, "b"));
+        history.end_group();
+        history.record(insert(1, "c"));
+        history.end_group();
+        history.record(insert(2, "d"));
+        assert_eq!(texts(&history.undo().unwrap()), ["+d"]);
+        let group = history.undo().unwrap();
+        assert_eq!(texts(&group), ["-a", "+b", "+c"]);
+        assert_eq!(texts(&history.redo().unwrap()), ["-a", "+b", "+c"]);
+    }
+
+    #[test]
+    fn drops_empty_groups() {
+        let mut history = History::default();
+        history.record(insert(0, "a"));
+        history.begin_group();
+        history.end_group();
+        assert_eq!(texts(&history.undo().unwrap()), ["+a"]);
+        assert!(history.undo().is_none());
+    }
+
+    #[test]
+    fn recording_clears_redo() {
+        let mut history = History::default();
+        history.record(insert(0, "a"));
+        history.undo();
+        history.record(insert(0, "b"));
+        assert!(history.redo().is_none());
+    }
+
+    #[test]
+    fn tracks_the_saved_point() {
+        let mut history = History::default();
+        assert!(history.is_saved());
+        history.record(insert(0, "a"));
+        assert!(!history.is_saved());
+        history.mark_saved();
+        assert!(history.is_saved());
+        // Typing after a save starts a new step instead of extending the
+        // saved one.
+        history.record(insert(1, "b"));
+        assert!(!history.is_saved());
+        history.undo();
+        assert!(history.is_saved());
+        history.undo();
+        assert!(!history.is_saved());
+        history.redo();
+        assert!(history.is_saved());
+    }
+}
@@ -1,18 +1,45 @@
+mod buffer;
+mod config;
+mod diff;
 mod document;
 mod editor;
+mod fileformat;
 mod filetype;
+mod frame;
 mod highlighting;
+mod history;
+mod piece_table;
 mod row;
+mod search;
+mod swap;
 mod terminal;
+mod window;
 
 pub use row::Row;
+pub use buffer::Buffer;
+pub use config::Config;
+pub use config::LineNumbers;
 pub use document::Document;
 pub use editor::Editor;
 pub use editor::SearchDirection;
+pub use fileformat::Encoding;
+pub use fileformat::FileFormat;
+pub use fileformat::LineEnding;
 pub use filetype::FileType;
+pub use frame::Frame;
 pub use filetype::HighlightingOptions;
+pub use history::History;
+pub use history::Operation;
+pub use piece_table::PieceTable;
 pub use terminal::Terminal;
+pub use window::Layout;
+pub use window::Rect;
+pub use window::SplitDirection;
+pub use window::Window;
 pub use editor::Position;
+pub use search::Query;
+pub use search::Replacer;
+pub use search::SearchOptions;
 
 fn main() -> Result<(), std::io::Error> {
     Editor::default().run()
@@ -0,0 +1,428 @@
+use std::ops::Range;
+
+#[derive(PartialEq, Clone, Copy)]
+enum Source {
+    Original,
+    Added,
+}
+
+/// A run of the text, taken from one of the two buffers.
+#[derive(Clone, Copy)]
+struct Piece {
+    source: Source,
+    start: usize,
+    len: usize,
+    newlines: usize,
+}
+
+/// A node of the tree of pieces: a treap, ordered by position in the text
+/// and kept balanced by random priorities. Each node knows the bytes and
+/// newlines of its whole subtree, so finding an offset or a line follows a
+/// single path down.
+struct Node {
+    piece: Piece,
+    priority: u64,
+    len: usize,
+    newlines: usize,
+    left: Tree,
+    right: Tree,
+}
+
+type Tree = Option<Box<Node>>;
+
+impl Node {
+    fn new(piece: Piece, priority: u64) -> Box<Self> {
+        Box::new(Self {
+            piece,
+            priority,
+            len: piece.len,
+            newlines: piece.newlines,
+            left: None,
+            right: None,
+        })
+    }
+
+    fn update(&mut self) {
+        self.len = len(&self.left) + self.piece.len + len(&self.right);
+        self.newlines = newlines(&self.left) + self.piece.newlines + newlines(&self.right);
+    }
+}
+
+fn len(tree: &Tree) -> usize {
+    tree.as_ref().map_or(0, |node| node.len)
+}
+
+fn newlines(tree: &Tree) -> usize {
+    tree.as_ref().map_or(0, |node| node.newlines)
+}
+
+/// Joins two trees, with all of `left` before all of `right`.
+fn merge(left: Tree, right: Tree) -> Tree {
+    match (left, right) {
+        (None, tree) | (tree, None) => tree,
+        (Some(mut left), Some(mut right)) => {
+            if left.priority > right.priority {
+                left.right = merge(left.right.take(), Some(right));
+                left.update();
+                Some(left)
+            } else {
+                right.left = merge(Some(left), right.left.take());
+                right.update();
+                Some(right)
+            }
+        },
+    }
+}
+
+/// Grows the last piece of `tree` by `len` bytes holding `newlines`
+/// newlines, if it is the piece of the added buffer that ends at `start`.
+fn extend_last(tree: &mut Tree, start: usize, len: usize, newlines: usize) -> bool {
+    let Some(node) = tree else {
+        return false;
+    };
+    let extended = if node.right.is_some() {
+        extend_last(&mut node.right, start, len, newlines)
+    } else if node.piece.source == Source::Added && node.piece.start + node.piece.len == start {
+        node.piece.len += len;
+        node.piece.newlines += newlines;
+        true
+    } else {
+        false
+    };
+    if extended {
+        node.len += len;
+        node.newlines += newlines;
+    }
+    extended
+}
+
+/// Text stored as the file it was loaded from plus an append-only buffer
+/// of everything inserted since, stitched together by a tree of pieces.
+/// Edits only touch the tree; the buffers are never rewritten. Both
+/// buffers keep the offsets of their newlines, so lines are found by
+/// searching instead of scanning.
+pub struct PieceTable {
+    original: String,
+    original_newlines: Vec<usize>,
+    added: String,
+    added_newlines: Vec<usize>,
+    root: Tree,
+    /// State of the generator of node priorities.
+    seed: u64,
+}
+
+impl Default for PieceTable {
+    fn default() -> Self {
+        Self::from(String::new())
+    }
+}
+
+impl From<String> for PieceTable {
+    /// Takes over `text` as the original buffer, without copying it.
+    fn from(text: String) -> Self {
+        let mut table = Self {
+            original_newlines: text.match_indices('\n').map(|(index, _)| index).collect(),
+            original: text,
+            added: String::new(),
+            added_newlines: Vec::new(),
+            root: None,
+            seed: 0x2545_F491_4F6C_DD1D,
+        };
+        if !table.original.is_empty() {
+            let piece = table.piece(Source::Original, 0, table.original.len());
+            table.root = Some(Node::new(piece, table.next_priority()));
+        }
+        table
+    }
+}
+
+impl PieceTable {
+    pub fn len(&self) -> usize {
+        len(&self.root)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of lines, which is one more than the number of newlines.
+    pub fn line_count(&self) -> usize {
+        newlines(&self.root) + 1
+    }
+
+    /// Byte range of line `line`, without its newline.
+    pub fn line_range(&self, line: usize) -> Range<usize> {
+        let start = self.line_start(line);
+        let end = if line + 1 < self.line_count() {
+            self.line_start(line + 1) - 1
+        } else {
+            self.len()
+        };
+        start..end
+    }
+
+    /// The text in the byte range `range`.
+    pub fn slice(&self, range: Range<usize>) -> String {
+        let mut text = String::with_capacity(range.len());
+        self.collect(&self.root, range, &mut text);
+        text
+    }
+
+    /// Inserts `text` at byte offset `at`.
+    pub fn insert(&mut self, at: usize, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        let start = self.added.len();
+        self.added.push_str(text);
+        self.added_newlines
+            .extend(text.match_indices('\n').map(|(index, _)| start + index));
+        let piece = self.piece(Source::Added, start, text.len());
+        let root = self.root.take();
+        let (mut before, after) = self.split(root, at);
+        // Typing extends the piece of the previous keystroke instead of
+        // adding a piece per character.
+        if !extend_last(&mut before, start, piece.len, piece.newlines) {
+            let node = Node::new(piece, self.next_priority());
+            before = merge(before, Some(node));
+        }
+        self.root = merge(before, after);
+    }
+
+    /// Removes the text in the byte range `range` and returns it.
+    pub fn delete(&mut self, range: Range<usize>) -> String {
+        let range = range.start.min(self.len())..range.end.min(self.len());
+        if range.is_empty() {
+            return String::new();
+        }
+        let root = self.root.take();
+        let (before, rest) = self.split(root, range.start);
+        let (removed, after) = self.split(rest, range.len());
+        let mut text = String::with_capacity(range.len());
+        self.collect(&removed, 0..range.len(), &mut text);
+        self.root = merge(before, after);
+        text
+    }
+
+    /// Byte offset at which line `line` starts.
+    fn line_start(&self, line: usize) -> usize {
+        if line == 0 {
+            return 0;
+        }
+        // Looks for the newline that ends the line before.
+        let mut wanted = line;
+        let mut offset = 0;
+        let mut tree = &self.root;
+        while let Some(node) = tree {
+            let left = newlines(&node.left);
+            if wanted <= left {
+                tree = &node.left;
+                continue;
+            }
+            wanted -= left;
+            offset += len(&node.left);
+            if wanted <= node.piece.newlines {
+                let newlines = self.newline_offsets(node.piece.source);
+                let first = newlines.partition_point(|&index| index < node.piece.start);
+                return offset + newlines[first + wanted - 1] + 1 - node.piece.start;
+            }
+            wanted -= node.piece.newlines;
+            offset += node.piece.len;
+            tree = &node.right;
+        }
+        self.len()
+    }
+
+    /// Appends the text of `tree` in `range`, relative to its start.
+    fn collect(&self, tree: &Tree, range: Range<usize>, text: &mut String) {
+        let Some(node) = tree else {
+            return;
+        };
+        if range.is_empty() {
+            return;
+        }
+        let piece_start = len(&node.left);
+        let piece_end = piece_start + node.piece.len;
+        if range.start < piece_start {
+            self.collect(&node.left, range.start..range.end.min(piece_start), text);
+        }
+        if range.start < piece_end && range.end > piece_start {
+            let from = range.start.max(piece_start) - piece_start;
+            let to = range.end.min(piece_end) - piece_start;
+            text.push_str(&self.text(&node.piece)[from..to]);
+        }
+        if range.end > piece_end {
+            self.collect(&node.right, range.start.saturating_sub(piece_end)..range.end - piece_end, text);
+        }
+    }
+
+    /// Splits `tree` into the text before byte offset `at` and the rest,
+    /// cutting the piece that spans `at` in two.
+    fn split(&mut self, tree: Tree, at: usize) -> (Tree, Tree) {
+        let Some(mut node) = tree else {
+            return (None, None);
+        };
+        let piece_start = len(&node.left);
+        let piece_end = piece_start + node.piece.len;
+        if at <= piece_start {
+            let (left, right) = self.split(node.left.take(), at);
+            node.left = right;
+            node.update();
+            (left, Some(node))
+        } else if at >= piece_end {
+            let (left, right) = self.split(node.right.take(), at - piece_end);
+            node.right = left;
+            node.update();
+            (Some(node), right)
+        } else {
+            let Piece { source, start, len, .. } = node.piece;
+            let head = at - piece_start;
+            node.piece = self.piece(source, start, head);
+            let tail = Node::new(self.piece(source, start + head, len - head), self.next_priority());
+            let right = merge(Some(tail), node.right.take());
+            node.update();
+            (Some(node), right)
+        }
+    }
+
+    fn piece(&self, source: Source, start: usize, len: usize) -> Piece {
+        let newlines = self.newline_offsets(source);
+        let count = newlines.partition_point(|&index| index < start + len)
+            - newlines.partition_point(|&index| index < start);
+        Piece { source, start, len, newlines: count }
+    }
+
+    fn text(&self, piece: &Piece) -> &str {
+        let buffer = match piece.source {
+            Source::Original => &self.original,
+            Source::Added => &self.added,
+        };
+        &buffer[piece.start..piece.start + piece.len]
+    }
+
+    fn newline_offsets(&self, source: Source) -> &[usize] {
+        match source {
+            Source::Original => &self.original_newlines,
+            Source::Added => &self.added_newlines,
+        }
+    }
+
+    fn next_priority(&mut self) -> u64 {
+        // xorshift64
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 7;
+        self.seed ^= self.seed << 17;
+        self.seed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(table: &PieceTable) -> String {
+        table.slice(0..table.len())
+    }
+
+    fn lines(table: &PieceTable) -> Vec<String> {
+        (0..table.line_count())
+            .map(|line| table.slice(table.line_range(line)))
+            .collect()
+    }
+
+    fn pieces(tree: &Tree) -> usize {
+        tree.as_ref()
+            .map_or(0, |node| pieces(&node.left) + 1 + pieces(&node.right))
+    }
+
+    fn depth(tree: &Tree) -> usize {
+        tree.as_ref()
+            .map_or(0, |node| depth(&node.left).max(depth(&node.right)) + 1)
+    }
+
+    #[test]
+    fn indexes_the_lines_of_the_original() {
+        let table = PieceTable::from("one\ntwo\n\nfour".to_string());
+        assert_eq!(lines(&table), ["one", "two", "", "four"]);
+        assert_eq!(PieceTable::default().line_count(), 1);
+    }
+
+    #[test]
+    fn inserts_into_the_middle_of_a_piece() {
+        let mut table = PieceTable::from("one\ntwo\n".to_string());
+        table.insert(5, "X\nY");
+        assert_eq!(text(&table), "one\ntX\nYwo\n");
+        assert_eq!(lines(&table), ["one", "tX", "Ywo", ""]);
+    }
+
+    #[test]
+    fn typing_extends_a_single_piece() {
+        let mut table = PieceTable::from("ab".to_string());
+        for (index, c) in ["x", "y", "z"].iter().enumerate() {
+            table.insert(1 + index, c);
+        }
+        assert_eq!(text(&table), "axyzb");
+        assert_eq!(pieces(&table.root), 3);
+    }
+
+    #[test]
+    fn inserts_at_both_ends() {
+        let mut table = PieceTable::default();
+        table.insert(0, "b\n");
+        table.insert(0, "a\n");
+        table.insert(4, "c");
+        assert_eq!(lines(&table), ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn deletes_across_pieces_and_lines() {
+        let mut table = PieceTable::from("one\ntwo\nthree\n".to_string());
+        table.insert(4, "new\n");
+        assert_eq!(table.delete(2..10), "e\nnew\ntw");
+        assert_eq!(text(&table), "ono\nthree\n");
+        assert_eq!(lines(&table), ["ono", "three", ""]);
+        assert_eq!(table.delete(0..table.len()), "ono\nthree\n");
+        assert_eq!(lines(&table), [""]);
+    }
+
+    #[test]
+    fn matches_a_string_through_many_edits() {
+        let mut table = PieceTable::from("first\nsecond\n".to_string());
+        let mut model = text(&table);
+        let mut seed = 7_usize;
+        for step in 0..2000 {
+            seed = seed.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1_442_695_040_888_963_407);
+            let at = (seed >> 33) % (model.len() + 1);
+            if step % 3 == 0 && at < model.len() {
+                let end = (at + (seed >> 20) % 7).min(model.len());
+                assert_eq!(table.delete(at..end), model[at..end]);
+                model.replace_range(at..end, "");
+            } else {
+                let inserted = if step % 5 == 0 { "line\n" } else { "ab" };
+                table.insert(at, inserted);
+                model.insert_str(at, inserted);
+            }
+        }
+        assert_eq!(text(&table), model);
+        assert_eq!(lines(&table), model.split('\n').collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn stays_shallow_after_an_edit_on_every_line() {
+        let lines = 50_000;
+        let mut table = PieceTable::from("word\n".repeat(lines));
+        // What replacing a word on every line does: a delete and an insert
+        // per line, each leaving new pieces behind.
+        for line in 0..lines {
+            let range = table.line_range(line);
+            table.delete(range.start..range.start + 1);
+            table.insert(range.start, "W");
+        }
+        assert_eq!(table.slice(table.line_range(lines - 1)), "Word");
+        assert_eq!(table.line_count(), lines + 1);
+        assert!(pieces(&table.root) > lines);
+        // A balanced tree of n pieces is about log2(n) deep; a treap stays
+        // within a small multiple of that.
+        assert!(depth(&table.root) < 60, "depth {}", depth(&table.root));
+    }
+}
@@ -1,199 +1,248 @@
+use crate::frame;
 use crate::highlighting;
+use crate::Frame;
 use crate::HighlightingOptions;
+use crate::Query;
 use crate::SearchDirection;
 use std::cmp;
-use crossterm::style::{SetForegroundColor, Color};
+use std::mem;
+use std::ops::Range;
 use unicode_segmentation::UnicodeSegmentation;
 
+/// A line of a document, as handed out for drawing and searching. The text
+/// itself lives in the document; editing goes through the document too.
 #[derive(Default)]
 pub struct Row {
     string: String,
     highlighting: Vec<highlighting::Type>,
-    pub is_highlighted: bool,
     len: usize,
+    is_ascii: bool,
 }
 
 impl From<&str> for Row {
     fn from(slice: &str) -> Self {
+        Self::from(slice.to_string())
+    }
+}
+
+impl From<String> for Row {
+    fn from(string: String) -> Self {
+        let is_ascii = string.is_ascii();
+        let len = if is_ascii {
+            string.len()
+        } else {
+            string.graphemes(true).count()
+        };
         Self {
-            string: slice.to_string(),
+            string,
             highlighting: Vec::new(),
-            is_highlighted: false,
-            len: slice.graphemes(true).count(),
+            len,
+            is_ascii,
         }
     }
 }
 
 impl Row {
-    pub fn render(&self, start: usize, end: usize) -> String {
-        let end = cmp::min(self.string.len(), end);
-        let start = cmp::min(start, end);
-        let mut result = String::new();
-        let mut current_highlighting = &highlighting::Type::None;
-        for (index, grapheme) in self.string[..]
-            .graphemes(true)
-            .enumerate()
-            .skip(start)
-            .take(end - start)
-        {
-            if let Some(c) = grapheme.chars().next() {
-                let highlight_type = self
-                    .highlighting
-                    .get(index)
-                    .unwrap_or(&highlighting::Type::None);
-                if highlight_type != current_highlighting {
-                    current_highlighting = highlight_type;
-                    let start_highlighting = 
-                        format!("{}", SetForegroundColor(highlight_type.to_color()));
-                    result.push_str(&start_highlighting);
-                }
-                if c == '\t' {
-                    result.push_str(" ");
-                } else {
-                    result.push(c);
-                }
-            }
-        }
-        let end_highlight = format!("{}", SetForegroundColor(Color::Reset));
-        result.push_str(&end_highlight[..]);
-        result
+    /// Attaches highlighting computed earlier by `highlight`.
+    pub fn with_highlighting(mut self, highlighting: Vec<highlighting::Type>) -> Self {
+        self.highlighting = highlighting;
+        self
     }
 
-    pub fn len(&self) -> usize {
-        self.len
+    /// Hands the highlighting over to the document, which keeps it while
+    /// the line is unchanged.
+    pub fn take_highlighting(&mut self) -> Vec<highlighting::Type> {
+        mem::take(&mut self.highlighting)
     }
 
-    pub fn is_empty(&self) -> bool {
-        self.string.is_empty()
+    /// Draws the screen columns `start..end` of the row at the frame's pen,
+    /// with tabs expanded to the next multiple of `tab_width`.
+    pub fn render(
+        &self,
+        frame: &mut Frame,
+        start: usize,
+        end: usize,
+        selection: Option<(usize, usize)>,
+        tab_width: usize,
+    ) {
+        let mut column = 0;
+        for (index, grapheme) in self.as_str().graphemes(true).enumerate() {
+            if column >= end {
+                break;
+            }
+            let next_column = column + grapheme_width(grapheme, column, tab_width);
+            if next_column <= start {
+                column = next_column;
+                continue;
+            }
+            let highlight_type = self
+                .highlighting
+                .get(index)
+                .unwrap_or(&highlighting::Type::None);
+            frame.set_reverse(selection.is_some_and(|(from, to)| index >= from && index < to));
+            frame.set_fg_color(highlight_type.to_color());
+            // Tabs, and wide graphemes cut off at either edge, are drawn as
+            // blanks over the part of them that is visible.
+            if grapheme == "\t" || column < start || next_column > end {
+                let visible = cmp::min(next_column, end) - cmp::max(column, start);
+                frame.print(&" ".repeat(visible));
+            } else if grapheme.starts_with(char::is_control) {
+                // Control characters would move the terminal's cursor, so
+                // they are shown as a placeholder.
+                frame.print("\u{fffd}");
+            } else {
+                frame.print(grapheme);
+            }
+            column = next_column;
+        }
+        frame.set_reverse(false);
+        frame.reset_fg_color();
     }
 
-    pub fn insert(&mut self, at: usize, c: char) {
-        if at >= self.len() {
-            self.string.push(c);
-            self.len += 1;
-            return;
+    /// Screen column at which the grapheme `x` starts.
+    pub fn render_x(&self, x: usize, tab_width: usize) -> usize {
+        if self.is_ascii && !self.as_str().contains('\t') {
+            return cmp::min(x, self.len);
+        }
+        let mut column = 0;
+        for grapheme in self.as_str().graphemes(true).take(x) {
+            column += grapheme_width(grapheme, column, tab_width);
         }
+        column
+    }
 
-        let mut result: String = String::new();
-        let mut length = 0;
-        for (index, grapheme) in self.string.graphemes(true).enumerate() {
-            length += 1;
-            if index == at {
-                length += 1;
-                result.push(c);
+    /// Index of the grapheme drawn at screen column `column`, or the row
+    /// length past its end.
+    pub fn x_at(&self, column: usize, tab_width: usize) -> usize {
+        let mut current = 0;
+        for (index, grapheme) in self.as_str().graphemes(true).enumerate() {
+            current += grapheme_width(grapheme, current, tab_width);
+            if current > column {
+                return index;
             }
-            result.push_str(grapheme);
         }
-        self.len = length;
-        self.string = result;
+        self.len
     }
 
-    pub fn delete(&mut self, at: usize) {
-        if at >= self.len() {
-            return;
-        }
-        let mut result = String::new();
-        let mut length = 0;
-        for (index, grapheme) in self.string.graphemes(true).enumerate() {
-            if index != at {
-                length += 1;
-                result.push_str(grapheme)
+    /// Column ranges of the screen lines the row takes when wrapped to
+    /// `width` columns. Lines break after whitespace where possible, and
+    /// whitespace at a break may hang past `width`.
+    pub fn wrap(&self, width: usize, tab_width: usize) -> Vec<Range<usize>> {
+        let mut lines = Vec::new();
+        let mut start = 0;
+        let mut break_at = None;
+        let mut column = 0;
+        for grapheme in self.as_str().graphemes(true) {
+            let next_column = column + grapheme_width(grapheme, column, tab_width);
+            let blank = grapheme.chars().all(char::is_whitespace);
+            while !blank && column > start && next_column - start > width {
+                let end = break_at.filter(|&at| at > start).unwrap_or(column);
+                lines.push(start..end);
+                start = end;
+                break_at = None;
             }
+            if blank {
+                break_at = Some(next_column);
+            }
+            column = next_column;
         }
-        self.len = length;
-        self.string = result;
+        lines.push(start..column);
+        lines
     }
 
-    pub fn append(&mut self, new: &Self) {
-        self.string = format!("{}{}", self.string, new.string);
-        self.len += new.len;
+    pub fn len(&self) -> usize {
+        self.len
     }
 
-    pub fn split(&mut self, at: usize) -> Self {
-        let mut row = String::new();
-        let mut length = 0;
-        let mut splitted_row = String::new();
-        let mut splitted_length = 0;
-        for (index, grapheme) in self.string.graphemes(true).enumerate() {
-            if index < at {
-                length += 1;
-                row.push_str(grapheme);
-            } else {
-                splitted_length += 1;
-                splitted_row.push_str(grapheme);
+    pub fn is_empty(&self) -> bool {
+        self.as_str().is_empty()
+    }
+
+    /// Byte offset of the grapheme at `at`, or the end of the row past it.
+    /// ASCII rows have one byte per grapheme, so no scan is needed for them.
+    pub fn byte_index(&self, at: usize) -> usize {
+        let string = self.as_str();
+        if self.is_ascii {
+            return cmp::min(at, string.len());
+        }
+        string
+            .grapheme_indices(true)
+            .nth(at)
+            .map_or(string.len(), |(index, _)| index)
+    }
+
+    /// Index of the grapheme that starts at or contains `byte_index`.
+    fn grapheme_index(&self, byte_index: usize) -> usize {
+        if self.is_ascii {
+            return byte_index;
+        }
+        let string = self.as_str();
+        let mut index = 0;
+        for (start, grapheme) in string.grapheme_indices(true) {
+            if start + grapheme.len() > byte_index {
+                return index;
             }
+            index += 1;
         }
+        index
+    }
 
-        self.string = row;
-        self.len = length;
-        self.is_highlighted = false;
+    pub fn substring(&self, start: usize, end: usize) -> String {
+        let start = self.byte_index(start);
+        let end = cmp::max(start, self.byte_index(end));
+        self.as_str()[start..end].to_string()
+    }
 
-        Self {
-            string: splitted_row,
-            len: splitted_length,
-            is_highlighted: false,
-            highlighting: Vec::new(),
+    pub fn grapheme(&self, at: usize) -> Option<&str> {
+        if at >= self.len {
+            return None;
         }
+        let start = self.byte_index(at);
+        let end = self.byte_index(at.saturating_add(1));
+        Some(&self.as_str()[start..end])
     }
 
-    pub fn as_bytes(&self) -> &[u8] {
-        self.string.as_bytes()
+    pub fn as_str(&self) -> &str {
+        &self.string
     }
 
-    pub fn find(&self, query: &str, at: usize, direction: SearchDirection) -> Option<usize> {
+    /// Grapheme ranges of every match of `query` in the row.
+    pub fn find_all(&self, query: &Query) -> Vec<(usize, usize)> {
+        let string = self.as_str();
+        query
+            .matches(string)
+            .into_iter()
+            .filter(|range| !query.whole_word() || is_word(string, range.start, range.end))
+            .map(|range| (self.grapheme_index(range.start), self.grapheme_index(range.end)))
+            .filter(|(start, end)| start < end)
+            .collect()
+    }
+
+    pub fn find(&self, query: &Query, at: usize, direction: SearchDirection) -> Option<usize> {
         if at > self.len || query.is_empty() {
             return None;
         }
-        let start = if direction == SearchDirection::Forward {
-            at
-        } else {
-            0
-        };
-
-        let end = if direction == SearchDirection::Forward {
-            self.len
+        let matches = self.find_all(query);
+        if direction == SearchDirection::Forward {
+            matches.iter().find(|(start, _)| *start >= at)
         } else {
-            at
-        };
-
-        let substring: String = self.string[..]
-            .graphemes(true)
-            .skip(start)
-            .take(end - start)
-            .collect();
-        let matching_byte_index = if direction == SearchDirection::Forward {
-            substring.find(query)
-        } else {
-            substring.rfind(query)
-        };
-
-        if let Some(matching_byte_index) = matching_byte_index {
-            for (grapheme_index, (byte_index, _)) in
-                substring[..].grapheme_indices(true).enumerate()
-            {
-                if matching_byte_index == byte_index {
-                    return Some(start + grapheme_index);
-                }
-            }
+            matches.iter().rev().find(|(_, end)| *end <= at)
         }
-        None
+        .map(|(start, _)| *start)
+    }
+
+    /// Replacement text for the match of `query` starting at grapheme `at`.
+    pub fn expand(&self, query: &Query, at: usize, replacement: &str) -> String {
+        query.expand(self.as_str(), self.byte_index(at), replacement)
     }
 
-    fn highlight_match(&mut self, word: &Option<String>) {
+    fn highlight_match(&mut self, word: &Option<Query>) {
         if let Some(word) = word {
-            if word.is_empty() {
-                return;
-            }
-            let mut index = 0;
-            while let Some(search_match) = self.find(word, index, SearchDirection::Forward) {
-                if let Some(next_index) = search_match.checked_add(word[..].graphemes(true).count())
-                {
-                    for i in search_match..next_index  {
-                        self.highlighting[i] = highlighting::Type::Match;
+            for (start, end) in self.find_all(word) {
+                for i in start..end {
+                    if let Some(hl_type) = self.highlighting.get_mut(i) {
+                        *hl_type = highlighting::Type::Match;
                     }
-                    index = next_index;
-                } else {
-                    break;
                 }
             }
         }
@@ -262,7 +311,7 @@ impl Row {
         self.highlight_keywords(
             index,
             chars,
-            &opts.primary_keywords(),
+            opts.primary_keywords(),
             highlighting::Type::PrimaryKeywords,
         )
     }
@@ -276,7 +325,7 @@ impl Row {
         self.highlight_keywords(
             index,
             chars,
-            &opts.secondary_keywords(),
+            opts.secondary_keywords(),
             highlighting::Type::SecondaryKeywords,
         )
     }
@@ -341,7 +390,7 @@ impl Row {
             if let Some(next_char) = chars.get(index.saturating_add(1)) {
                 if *next_char == '*' {
                     let closing_index = 
-                        if let Some(closing_index) = self.string[*index + 2..].find("*/") {
+                        if let Some(closing_index) = self.as_str()[*index + 2..].find("*/") {
                             *index + closing_index + 4
                         } else {
                             chars.len()
@@ -415,29 +464,20 @@ impl Row {
         false
     }
 
+    /// Highlights the row and tells whether a multiline comment is still
+    /// open at its end.
     pub fn highlight(
         &mut self, 
         opts: &HighlightingOptions, 
-        word: &Option<String>,
+        word: &Option<Query>,
         start_with_comment: bool,
     ) -> bool {
-        let chars: Vec<char> = self.string.chars().collect();
-        if self.is_highlighted && word.is_none() {
-            if let Some(hl_type) = self.highlighting.last() {
-                if *hl_type == highlighting::Type::MultilineComment
-                    && self.string.len() > 1
-                    && self.string[self.string.len() - 2..] == *"*/"
-                {
-                    return true;
-                }
-            }
-            return false
-        }
+        let chars: Vec<char> = self.as_str().chars().collect();
         self.highlighting = Vec::new();
         let mut index = 0;
         let mut in_ml_comment = start_with_comment;
         if in_ml_comment {
-            let closing_index = if let Some(closing_index) = self.string.find("*/") {
+            let closing_index = if let Some(closing_index) = self.as_str().find("*/") {
                 closing_index + 2
             } else {
                 chars.len()
@@ -448,7 +488,7 @@ impl Row {
             index = closing_index;
         }
         while let Some(c) = chars.get(index) {
-            if self.highlight_multiline_comment(&mut index, &opts, *c, &chars) {
+            if self.highlight_multiline_comment(&mut index, opts, *c, &chars) {
                 in_ml_comment = true;
                 continue;
             }
@@ -466,14 +506,106 @@ impl Row {
             index += 1;
         }
         self.highlight_match(word);
-        if in_ml_comment && &self.string[self.string.len().saturating_sub(2)..] != "*/" {
-            return true;
-        }
-        self.is_highlighted = true;
-        false
+        in_ml_comment && !self.as_str().ends_with("*/")
+    }
+}
+
+/// Number of screen columns `grapheme` takes when drawn at `column`.
+/// Full-width characters and emoji take two columns.
+fn grapheme_width(grapheme: &str, column: usize, tab_width: usize) -> usize {
+    if grapheme == "\t" {
+        tab_width - column % tab_width
+    } else {
+        frame::cell_width(grapheme)
     }
 }
 
 fn is_separator(c: char) -> bool {
     c.is_ascii_punctuation() || c.is_ascii_whitespace()
-}
\ No newline at end of file
+}
+
+/// Whether `string[start..end]` is bounded by separators or the row edges.
+fn is_word(string: &str, start: usize, end: usize) -> bool {
+    let before = string[..start].chars().next_back();
+    let after = string[end..].chars().next();
+    before.is_none_or(is_separator) && after.is_none_or(is_separator)
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SearchOptions;
+
+    fn find_all(text: &str, pattern: &str, options: SearchOptions) -> Vec<(usize, usize)> {
+        Row::from(text).find_all(&Query::new(pattern, options).unwrap())
+    }
+
+    #[test]
+    fn whole_words_are_bounded_by_separators() {
+        let options = SearchOptions { whole_word: true, ..SearchOptions::default() };
+        assert_eq!(find_all("cat concat cat. cats (cat)", "cat", options), [(0, 3), (11, 14), (22, 25)]);
+        assert!(is_word("a-b", 0, 1));
+        assert!(!is_word("ab", 0, 1));
+        // Only ASCII punctuation and whitespace separate words.
+        assert!(find_all("écat", "cat", options).is_empty());
+    }
+
+    #[test]
+    fn ignores_case_beyond_ascii() {
+        let options = SearchOptions { ignore_case: true, ..SearchOptions::default() };
+        assert_eq!(find_all("Ärger ärger ÄRGER", "ärger", options), [(0, 5), (6, 11), (12, 17)]);
+        let exact = SearchOptions::default();
+        assert_eq!(find_all("Ärger ärger", "ärger", exact), [(6, 11)]);
+        let words = SearchOptions { ignore_case: true, whole_word: true, ..SearchOptions::default() };
+        assert_eq!(find_all("Cat CATS cat", "cat", words), [(0, 3), (9, 12)]);
+    }
+
+    #[test]
+    fn matches_are_counted_in_graphemes() {
+        assert_eq!(find_all("héllo wörld", "wörld", SearchOptions::default()), [(6, 11)]);
+        assert_eq!(find_all("e\u{301}x", "x", SearchOptions::default()), [(1, 2)]);
+    }
+
+    #[test]
+    fn wraps_after_whitespace() {
+        assert_eq!(Row::from("hello world foo").wrap(8, 4), [0..6, 6..12, 12..15]);
+        // Whitespace at a break hangs past the width instead of wrapping.
+        assert_eq!(Row::from("abc   d").wrap(3, 4), [0..6, 6..7]);
+        let empty = Row::default().wrap(8, 4);
+        assert!(empty.len() == 1 && empty[0].is_empty());
+    }
+
+    #[test]
+    fn breaks_words_longer_than_the_width() {
+        assert_eq!(Row::from("abcdefghij").wrap(4, 4), [0..4, 4..8, 8..10]);
+        assert_eq!(Row::from("ab cdefghij").wrap(4, 4), [0..3, 3..7, 7..11]);
+    }
+
+    #[test]
+    fn wraps_tabs_by_their_stops() {
+        assert_eq!(Row::from("ab\tcd").wrap(4, 4), [0..4, 4..6]);
+        assert_eq!(Row::from("abc\td").wrap(3, 4), [0..4, 4..5]);
+    }
+
+    #[test]
+    fn moves_wide_characters_that_do_not_fit_to_the_next_line() {
+        assert_eq!(Row::from("ab漢字").wrap(3, 4), [0..2, 2..4, 4..6]);
+        assert_eq!(Row::from("漢字").wrap(1, 4), [0..2, 2..4]);
+    }
+
+    #[test]
+    fn converts_between_graphemes_and_columns() {
+        let tabbed = Row::from("a\tb");
+        assert_eq!(tabbed.render_x(2, 4), 4);
+        assert_eq!(tabbed.render_x(3, 4), 5);
+        assert_eq!(tabbed.x_at(2, 4), 1);
+        assert_eq!(tabbed.x_at(4, 4), 2);
+        let wide = Row::from("漢字x");
+        assert_eq!(wide.render_x(1, 4), 2);
+        assert_eq!(wide.render_x(3, 4), 5);
+        assert_eq!(wide.x_at(1, 4), 0);
+        assert_eq!(wide.x_at(2, 4), 1);
+        assert_eq!(wide.x_at(4, 4), 2);
+        assert_eq!(wide.x_at(10, 4), 3);
+        assert_eq!(Row::from("abc").render_x(10, 4), 3);
+    }
+}
@@ -0,0 +1,277 @@
+use crate::Document;
+use crate::Position;
+use regex::{Regex, RegexBuilder};
+use std::ops::Range;
+
+#[derive(Default, Clone, Copy)]
+pub struct SearchOptions {
+    pub regex: bool,
+    pub ignore_case: bool,
+    pub whole_word: bool,
+}
+
+impl SearchOptions {
+    /// Short description of the enabled options, e.g. "regex, whole word".
+    pub fn describe(&self) -> String {
+        let mut flags = Vec::new();
+        if self.regex {
+            flags.push("regex");
+        }
+        if self.ignore_case {
+            flags.push("ignore case");
+        }
+        if self.whole_word {
+            flags.push("whole word");
+        }
+        flags.join(", ")
+    }
+}
+
+pub struct Query {
+    text: String,
+    options: SearchOptions,
+    regex: Option<Regex>,
+}
+
+impl Query {
+    pub fn new(text: &str, options: SearchOptions) -> Result<Self, regex::Error> {
+        // Case-insensitive plain text search goes through an escaped regex
+        // so that Unicode case folding is handled the same way in both modes.
+        let regex = if options.regex || options.ignore_case {
+            let pattern = if options.regex {
+                text.to_string()
+            } else {
+                regex::escape(text)
+            };
+            Some(
+                RegexBuilder::new(&pattern)
+                    .case_insensitive(options.ignore_case)
+                    .build()?,
+            )
+        } else {
+            None
+        };
+        Ok(Self {
+            text: text.to_string(),
+            options,
+            regex,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty()
+    }
+
+    pub fn whole_word(&self) -> bool {
+        self.options.whole_word
+    }
+
+    /// Byte ranges of the non-overlapping matches in `haystack`. Empty regex
+    /// matches are skipped since there is nothing to jump to or highlight.
+    pub fn matches(&self, haystack: &str) -> Vec<Range<usize>> {
+        if self.is_empty() {
+            return Vec::new();
+        }
+        match &self.regex {
+            Some(regex) => regex
+                .find_iter(haystack)
+                .filter(|found| !found.is_empty())
+                .map(|found| found.range())
+                .collect(),
+            None => haystack
+                .match_indices(&self.text)
+                .map(|(index, found)| index..index + found.len())
+                .collect(),
+        }
+    }
+
+    /// Replacement text for the match starting at byte `start` of
+    /// `haystack`. In regex mode `$1`, `${name}` and so on are expanded from
+    /// the match's capture groups.
+    pub fn expand(&self, haystack: &str, start: usize, replacement: &str) -> String {
+        let mut result = String::new();
+        let captures = self
+            .regex
+            .as_ref()
+            .filter(|_| self.options.regex)
+            .and_then(|regex| regex.captures_at(haystack, start));
+        match captures {
+            Some(captures) => captures.expand(replacement, &mut result),
+            None => result.push_str(replacement),
+        }
+        result
+    }
+}
+
+/// Steps through the matches of a replace that starts at `origin`. Once
+/// the end of the document is reached it goes on from the top, up to where
+/// it started, so that every match is offered once.
+pub struct Replacer {
+    origin: Position,
+    at: Position,
+    /// Where the second pass ends, once it has begun.
+    stop: Option<Position>,
+}
+
+impl Replacer {
+    pub fn new(origin: Position) -> Self {
+        Self {
+            origin,
+            at: origin,
+            stop: None,
+        }
+    }
+
+    /// The start and end of the next match to offer.
+    pub fn next(&mut self, document: &Document, query: &Query) -> Option<(Position, Position)> {
+        loop {
+            let found = document
+                .find_range(query, &self.at)
+                .filter(|(start, _)| self.stop.is_none_or(|stop| (start.y, start.x) < (stop.y, stop.x)));
+            if found.is_some() || self.stop.is_some() {
+                return found;
+            }
+            self.stop = Some(self.origin);
+            self.at = Position::default();
+        }
+    }
+
+    /// Leaves the match ending at `end` as it is.
+    pub fn skip(&mut self, end: Position) {
+        self.at = end;
+    }
+
+    /// Replaces the match from `start` to `end` with `replacement`, with
+    /// capture groups expanded, and returns where the inserted text ends.
+    pub fn replace(
+        &mut self,
+        document: &mut Document,
+        query: &Query,
+        (start, end): (Position, Position),
+        replacement: &str,
+    ) -> Position {
+        let text = document
+            .row(start.y)
+            .map(|row| row.expand(query, start.x, replacement))
+            .unwrap_or_default();
+        document.delete_range(&start, &end);
+        self.at = document.insert_str(&start, &text);
+        // Replacing text before the starting point moves it.
+        if let Some(stop) = self.stop.as_mut() {
+            if (end.y, end.x) >= (stop.y, stop.x) {
+                *stop = self.at;
+            } else {
+                if stop.y == end.y {
+                    stop.x = self.at.x + (stop.x - end.x);
+                }
+                stop.y = stop.y + self.at.y - end.y;
+            }
+        }
+        self.at
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(text: &str, regex: bool, ignore_case: bool) -> Query {
+        Query::new(text, SearchOptions { regex, ignore_case, whole_word: false }).unwrap()
+    }
+
+    fn found(query: &Query, haystack: &str) -> Vec<(usize, usize)> {
+        query.matches(haystack).into_iter().map(|range| (range.start, range.end)).collect()
+    }
+
+    #[test]
+    fn plain_text_is_matched_literally() {
+        assert_eq!(found(&query("a.b", false, false), "a.b axb a.b"), [(0, 3), (8, 11)]);
+        assert_eq!(found(&query("A.B", false, true), "a.b axb A.b"), [(0, 3), (8, 11)]);
+        assert!(query("", false, false).matches("abc").is_empty());
+    }
+
+    #[test]
+    fn regex_anchors_and_classes() {
+        assert_eq!(found(&query("^ab", true, false), "ab ab"), [(0, 2)]);
+        assert_eq!(found(&query("ab$", true, false), "ab ab"), [(3, 5)]);
+        assert_eq!(found(&query("[0-9]+", true, false), "a12 b3"), [(1, 3), (5, 6)]);
+        assert_eq!(found(&query(r"\bis\b", true, false), "this is"), [(5, 7)]);
+        assert_eq!(found(&query("[a-c]", true, true), "xBz"), [(1, 2)]);
+        assert!(Query::new("(", SearchOptions { regex: true, ..SearchOptions::default() }).is_err());
+    }
+
+    #[test]
+    fn empty_regex_matches_are_skipped() {
+        assert_eq!(found(&query("x*", true, false), "abxxc"), [(2, 4)]);
+        assert!(query("^", true, false).matches("abc").is_empty());
+    }
+
+    #[test]
+    fn expands_capture_groups_in_regex_mode_only() {
+        let regex = query(r"(\w+)@(?P<host>\w+)", true, false);
+        assert_eq!(regex.expand("mail me@home now", 5, "$1 at ${host}"), "me at home");
+        assert_eq!(regex.expand("mail me@home now", 5, "$$1"), "$1");
+        let plain = query("me@home", false, false);
+        assert_eq!(plain.expand("mail me@home now", 5, "$1"), "$1");
+        // Case-insensitive plain search uses a regex too, but no groups.
+        let folded = query("ME@HOME", false, true);
+        assert_eq!(folded.expand("mail me@home now", 5, "$0"), "$0");
+    }
+
+    /// Replaces every match of `pattern` in `text`, starting at `origin`,
+    /// and returns the new text and the number of replacements.
+    fn replace_all(text: &str, origin: Position, pattern: &str, replacement: &str) -> (String, usize) {
+        let mut document = Document::default();
+        document.insert_str(&Position::default(), text);
+        let query = Query::new(pattern, SearchOptions::default()).unwrap();
+        let mut replacer = Replacer::new(origin);
+        let mut replaced = 0;
+        while let Some(found) = replacer.next(&document, &query) {
+            replacer.replace(&mut document, &query, found, replacement);
+            replaced += 1;
+        }
+        (document.text(), replaced)
+    }
+
+    #[test]
+    fn replaces_a_match_around_the_origin_on_the_second_pass() {
+        let origin = Position { x: 2, y: 0 };
+        assert_eq!(replace_all("abcdef abcdef", origin, "abcdef", "X"), ("X X".to_string(), 2));
+    }
+
+    #[test]
+    fn does_not_replace_inside_replacements() {
+        assert_eq!(replace_all("a a a", Position::default(), "a", "aa"), ("aa aa aa".to_string(), 3));
+        let origin = Position { x: 3, y: 0 };
+        assert_eq!(replace_all("ab ab ab", origin, "ab", "abab"), ("abab abab abab".to_string(), 3));
+    }
+
+    #[test]
+    fn stops_where_it_started_after_replacing_before_it() {
+        let origin = Position { x: 1, y: 1 };
+        assert_eq!(replace_all("xx\nxx\nx", origin, "x", "yy\n"), ("yy\nyy\n\nyy\nyy\n\nyy\n".to_string(), 5));
+        let origin = Position { x: 2, y: 1 };
+        assert_eq!(replace_all("ab\nab ab\nab", origin, "ab", "c"), ("c\nc c\nc".to_string(), 4));
+    }
+
+    #[test]
+    fn empty_replacements_delete_the_matches() {
+        let origin = Position { x: 2, y: 0 };
+        assert_eq!(replace_all("a,b,c", origin, ",", ""), ("abc".to_string(), 2));
+    }
+
+    #[test]
+    fn skipped_matches_are_not_offered_again() {
+        let mut document = Document::default();
+        document.insert_str(&Position::default(), "a a");
+        let query = Query::new("a", SearchOptions::default()).unwrap();
+        let mut replacer = Replacer::new(Position { x: 1, y: 0 });
+        let (_, end) = replacer.next(&document, &query).unwrap();
+        replacer.skip(end);
+        let found = replacer.next(&document, &query).unwrap();
+        assert!(found.0 == Position::default());
+        replacer.replace(&mut document, &query, found, "b");
+        assert!(replacer.next(&document, &query).is_none());
+        assert_eq!(document.text(), "b a");
+    }
+}
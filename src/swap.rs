@@ -0,0 +1,56 @@
+use crate::document::write_atomically;
+use std::ffi::OsString;
+use std::fs::{self, Permissions};
+use std::io::Error;
+use std::path::{Path, PathBuf};
+
+/// Where the swap file of `file_name` lives: a hidden file next to it, so
+/// `notes.txt` is backed up in `.notes.txt.tte-swp`. The name is our own,
+/// so the swap files of other editors are never mistaken for ours.
+pub fn path(file_name: &str) -> PathBuf {
+    let path = Path::new(file_name);
+    let mut name = OsString::from(".");
+    name.push(path.file_name().unwrap_or(path.as_os_str()));
+    name.push(".tte-swp");
+    path.with_file_name(name)
+}
+
+pub fn exists(file_name: &str) -> bool {
+    path(file_name).exists()
+}
+
+/// Contents of the swap file of `file_name`.
+pub fn read(file_name: &str) -> Result<String, Error> {
+    fs::read_to_string(path(file_name))
+}
+
+/// Writes `text` to the swap file of `file_name`. It replaces the previous
+/// swap file only once complete, so a crash never leaves a truncated one.
+pub fn write(file_name: &str, text: &str) -> Result<(), Error> {
+    write_atomically(&path(file_name), text.as_bytes(), false, permissions(file_name))
+}
+
+/// The swap file holds the text of the file, so it is no more readable
+/// than the file itself. Swap files of new files are private to the owner.
+fn permissions(file_name: &str) -> Option<Permissions> {
+    match fs::metadata(file_name) {
+        Ok(metadata) => Some(metadata.permissions()),
+        Err(_) => private(),
+    }
+}
+
+#[cfg(unix)]
+fn private() -> Option<Permissions> {
+    use std::os::unix::fs::PermissionsExt;
+    Some(Permissions::from_mode(0o600))
+}
+
+#[cfg(not(unix))]
+fn private() -> Option<Permissions> {
+    None
+}
+
+/// Removes the swap file of `file_name`. Having none is not an error.
+pub fn remove(file_name: &str) {
+    let _ = fs::remove_file(path(file_name));
+}
@@ -1,31 +1,77 @@
+use crate::frame::{cell_width, Style};
+use crate::Frame;
 use crate::Position;
 use std::io::{Write, Error};
-use crossterm::terminal::{enable_raw_mode, Clear, ClearType};
-use crossterm::event::{read, Event, KeyEvent};
+use std::panic;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use crossterm::execute;
+use crossterm::terminal::{
+    disable_raw_mode, enable_raw_mode, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen,
+};
+use crossterm::event::{
+    poll, read, DisableBracketedPaste, DisableMouseCapture, EnableBracketedPaste, EnableMouseCapture, Event,
+};
 use crossterm::cursor::{MoveTo, Hide, Show};
-use crossterm::style::{SetForegroundColor, SetBackgroundColor, Color};
+use crossterm::style::{Attribute, ResetColor, SetAttribute, SetBackgroundColor, SetForegroundColor};
+
+/// Whether the alternate screen is in use, so that it can be left from the
+/// panic hook too.
+static ALTERNATE_SCREEN: AtomicBool = AtomicBool::new(false);
 
 pub struct Size {
     pub width: u16,
     pub height: u16,
 }
 
+/// Owns the terminal while the editor runs. The terminal is put back the
+/// way the shell expects it when this is dropped, and also from the panic
+/// hook, before the panic message is printed.
 pub struct Terminal {
     size: Size,
+    /// The frame on screen, or `None` when the screen has to be redrawn
+    /// from scratch.
+    previous: Option<Frame>,
+    cursor_visible: bool,
 }
 
 impl Terminal {
-    pub fn default() -> Result<Self, std::io::Error> {
+    /// Takes over the terminal. With `alternate_screen` the editor draws on
+    /// the alternate screen, leaving the shell's scrollback untouched.
+    pub fn new(alternate_screen: bool) -> Result<Self, std::io::Error> {
+        let default_hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            Self::restore();
+            default_hook(info);
+        }));
         enable_raw_mode()?;
+        execute!(std::io::stdout(), EnableMouseCapture, EnableBracketedPaste)?;
+        if alternate_screen {
+            execute!(std::io::stdout(), EnterAlternateScreen)?;
+            ALTERNATE_SCREEN.store(true, Ordering::SeqCst);
+        }
         let size = crossterm::terminal::size()?;
         Ok(Self {
             size: Size {
                 width: size.0,
                 height: size.1.saturating_sub(2),
-            }
+            },
+            previous: None,
+            cursor_visible: true,
         })
     }
 
+    /// Leaves raw mode and the alternate screen, stops capturing the mouse
+    /// and pastes, and shows the cursor again. Errors are ignored since this
+    /// runs while exiting, possibly from a panic.
+    pub fn restore() {
+        let _ = execute!(std::io::stdout(), ResetColor, Show, DisableMouseCapture, DisableBracketedPaste);
+        if ALTERNATE_SCREEN.swap(false, Ordering::SeqCst) {
+            let _ = execute!(std::io::stdout(), LeaveAlternateScreen);
+        }
+        let _ = disable_raw_mode();
+    }
+
     pub fn size(&self) -> &Size {
         &self.size
     }
@@ -45,40 +91,161 @@ impl Terminal {
         std::io::stdout().flush()
     }
 
-    pub fn read_key() -> Result<KeyEvent, Error> {
-        loop {
-            let event = read()?;
-            if let Event::Key(key_event) = event {
-                return Ok(key_event);
+    pub fn read_event() -> Result<Event, Error> {
+        read()
+    }
+
+    /// Waits up to `timeout` for an event and tells whether one is ready.
+    pub fn poll(timeout: Duration) -> Result<bool, Error> {
+        poll(timeout)
+    }
+
+    /// Records the new terminal size after a resize event. The next frame
+    /// is drawn in full since the terminal may have mangled the old one.
+    pub fn resize(&mut self, width: u16, height: u16) {
+        self.size = Size {
+            width,
+            height: height.saturating_sub(2),
+        };
+        self.previous = None;
+    }
+
+    /// Brings the screen up to date with `frame`, writing only the cells
+    /// that changed since the previous frame, in a single write.
+    pub fn draw(&mut self, frame: Frame) -> Result<(), Error> {
+        let previous = self.previous.take().filter(|previous| {
+            previous.width() == frame.width() && previous.height() == frame.height()
+        });
+        let output = frame_output(previous.as_ref(), &frame, &mut self.cursor_visible);
+        self.previous = Some(frame);
+        if output.is_empty() {
+            return Ok(());
+        }
+        let mut stdout = std::io::stdout();
+        stdout.write_all(output.as_bytes())?;
+        stdout.flush()
+    }
+}
+
+/// The escape sequences and text that turn the screen showing `previous`
+/// into `frame`, or the whole of `frame` on a cleared screen without a
+/// previous one. `cursor_visible` tells whether the terminal cursor is
+/// shown, and is updated to match `frame`.
+fn frame_output(previous: Option<&Frame>, frame: &Frame, cursor_visible: &mut bool) -> String {
+    let mut output = String::new();
+    if previous.is_none() {
+        output.push_str(&format!("{}", Clear(ClearType::All)));
+    }
+    let mut style = Style::default();
+    let mut pen = None;
+    for y in 0..frame.height() {
+        for x in 0..frame.width() {
+            let cell = frame.cell(x, y);
+            if cell.symbol.is_empty() || previous.is_some_and(|previous| previous.cell(x, y) == cell) {
+                continue;
             }
+            if pen != Some((x, y)) {
+                output.push_str(&format!("{}", MoveTo(x as u16, y as u16)));
+            }
+            push_style(&mut output, &style, &cell.style);
+            style = cell.style;
+            output.push_str(&cell.symbol);
+            pen = Some((x + cell_width(&cell.symbol), y));
+        }
+    }
+    push_style(&mut output, &style, &Style::default());
+    let moved = pen.is_some() || previous.is_none_or(|previous| previous.cursor() != frame.cursor());
+    match frame.cursor() {
+        Some(Position { x, y }) if moved => output.push_str(&format!("{}", MoveTo(x as u16, y as u16))),
+        _ => (),
+    }
+    let visible = frame.cursor().is_some();
+    if visible != *cursor_visible {
+        *cursor_visible = visible;
+        if visible {
+            output.push_str(&format!("{}", Show));
+        } else {
+            output.push_str(&format!("{}", Hide));
         }
     }
+    output
+}
 
-    pub fn cursor_hide() {
-        print!("{}", Hide);
+/// Appends the escape sequences that switch the terminal from style `from`
+/// to style `to`.
+fn push_style(output: &mut String, from: &Style, to: &Style) {
+    if from.fg != to.fg {
+        output.push_str(&format!("{}", SetForegroundColor(to.fg)));
+    }
+    if from.bg != to.bg {
+        output.push_str(&format!("{}", SetBackgroundColor(to.bg)));
     }
+    if from.reverse != to.reverse {
+        let attribute = if to.reverse {
+            Attribute::Reverse
+        } else {
+            Attribute::NoReverse
+        };
+        output.push_str(&format!("{}", SetAttribute(attribute)));
+    }
+}
 
-    pub fn cursor_show() {
-        print!("{}", Show);
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        Self::restore();
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    pub fn clear_current_line() {
-        print!("{}", Clear(ClearType::CurrentLine));
+    fn frame(text: &str, cursor: Option<Position>) -> Frame {
+        let mut frame = Frame::new(4, 2);
+        frame.print(text);
+        frame.set_cursor(cursor);
+        frame
     }
 
-    pub fn set_bg_color(color: Color)  {
-        print!("{}", SetBackgroundColor(color));
+    fn move_to(x: u16, y: u16) -> String {
+        format!("{}", MoveTo(x, y))
     }
 
-    pub fn reset_bg_color() {
-        print!("{}", SetBackgroundColor(Color::Reset));
+    #[test]
+    fn an_unchanged_frame_writes_nothing() {
+        let cursor = Some(Position { x: 1, y: 0 });
+        let mut visible = true;
+        assert_eq!(frame_output(Some(&frame("ab", cursor)), &frame("ab", cursor), &mut visible), "");
+        assert!(visible);
     }
 
-    pub fn set_fg_color(color: Color) {
-        print!("{}", SetForegroundColor(color));
+    #[test]
+    fn writes_only_the_changed_cell() {
+        let mut visible = false;
+        let output = frame_output(Some(&frame("abc", None)), &frame("abd", None), &mut visible);
+        assert_eq!(output, format!("{}d", move_to(2, 0)));
+        let mut visible = true;
+        let moved = Some(Position { x: 3, y: 1 });
+        let output = frame_output(Some(&frame("abc", None)), &frame("abc", moved), &mut visible);
+        assert_eq!(output, move_to(3, 1));
     }
 
-    pub fn reset_fg_color() {
-        print!("{}", SetForegroundColor(Color::Reset));
+    #[test]
+    fn replaces_wide_characters_cell_by_cell() {
+        let mut visible = false;
+        let output = frame_output(Some(&frame("漢x", None)), &frame("abx", None), &mut visible);
+        assert_eq!(output, format!("{}ab", move_to(0, 0)));
+        // The terminal fills the cell right of a wide character itself.
+        let output = frame_output(Some(&frame("abx", None)), &frame("漢x", None), &mut visible);
+        assert_eq!(output, format!("{}漢", move_to(0, 0)));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn a_first_frame_clears_the_screen_and_shows_the_cursor() {
+        let mut visible = false;
+        let output = frame_output(None, &frame("", Some(Position::default())), &mut visible);
+        assert!(output.starts_with(&format!("{}", Clear(ClearType::All))));
+        assert!(output.ends_with(&format!("{}{}", move_to(0, 0), Show)));
+        assert!(visible);
+    }
+}
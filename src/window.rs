@@ -0,0 +1,203 @@
+use crate::Position;
+
+/// A rectangle on the screen, in terminal cells.
+#[derive(Default, Clone, Copy)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+/// A view into one of the editor's buffers. Every window has its own cursor,
+/// scroll offset and selection, so the same buffer can be shown at two
+/// places at once.
+#[derive(Default, Clone)]
+pub struct Window {
+    pub buffer: usize,
+    pub cursor_position: Position,
+    pub offset: Position,
+    /// With soft wrapping, how many screen lines of the row at `offset.y`
+    /// are scrolled off the top.
+    pub top_line: usize,
+    pub selection_anchor: Option<Position>,
+    /// Screen area for the text. The window's status line sits right below it.
+    pub area: Rect,
+    /// Columns on the left of `area` taken by line numbers.
+    pub gutter: usize,
+}
+
+impl Window {
+    pub fn new(buffer: usize) -> Self {
+        Self {
+            buffer,
+            ..Self::default()
+        }
+    }
+
+    /// The selected range as (start, end), ordered by position in the document.
+    pub fn selection(&self) -> Option<(Position, Position)> {
+        let anchor = self.selection_anchor?;
+        let cursor = self.cursor_position;
+        if (anchor.y, anchor.x) == (cursor.y, cursor.x) {
+            return None;
+        }
+        if (anchor.y, anchor.x) < (cursor.y, cursor.x) {
+            Some((anchor, cursor))
+        } else {
+            Some((cursor, anchor))
+        }
+    }
+
+    /// Columns left for the text once the gutter is drawn.
+    pub fn text_width(&self) -> usize {
+        self.area.width.saturating_sub(self.gutter)
+    }
+
+    /// Scrolls so that the cursor is visible. `x` is the screen column the
+    /// cursor is drawn at, which differs from its grapheme index after a tab.
+    pub fn scroll(&mut self, x: usize) {
+        let y = self.cursor_position.y;
+        let width = self.text_width();
+        let height = self.area.height;
+        let offset = &mut self.offset;
+        if y < offset.y {
+            offset.y = y;
+        } else if y >= offset.y.saturating_add(height) {
+            offset.y = y.saturating_sub(height).saturating_add(1);
+        }
+        if x < offset.x {
+            offset.x = x;
+        } else if x >= offset.x.saturating_add(width) {
+            offset.x = x.saturating_sub(width).saturating_add(1);
+        }
+    }
+}
+
+#[derive(PartialEq, Clone, Copy)]
+pub enum SplitDirection {
+    /// One window above the other.
+    Horizontal,
+    /// Windows side by side.
+    Vertical,
+}
+
+/// How the screen is divided between windows. Leaves hold indices into the
+/// editor's window list.
+pub enum Layout {
+    Window(usize),
+    Split {
+        direction: SplitDirection,
+        first: Box<Layout>,
+        second: Box<Layout>,
+    },
+}
+
+impl Layout {
+    /// Splits the area of `window` in two, putting `new_window` in the
+    /// second half.
+    pub fn split(&mut self, window: usize, new_window: usize, direction: SplitDirection) {
+        match self {
+            Layout::Window(index) if *index == window => {
+                *self = Layout::Split {
+                    direction,
+                    first: Box::new(Layout::Window(window)),
+                    second: Box::new(Layout::Window(new_window)),
+                };
+            },
+            Layout::Window(_) => (),
+            Layout::Split { first, second, .. } => {
+                first.split(window, new_window, direction);
+                second.split(window, new_window, direction);
+            },
+        }
+    }
+
+    /// Removes `window`, giving its area to its sibling, and shifts the
+    /// indices of the windows after it down by one.
+    pub fn remove(&mut self, window: usize) {
+        self.detach(window);
+        self.shift(window);
+    }
+
+    fn detach(&mut self, window: usize) {
+        if let Layout::Split { first, second, .. } = self {
+            let sibling = match (&**first, &**second) {
+                (Layout::Window(index), _) if *index == window => second,
+                (_, Layout::Window(index)) if *index == window => first,
+                _ => {
+                    first.detach(window);
+                    second.detach(window);
+                    return;
+                },
+            };
+            *self = std::mem::replace(&mut **sibling, Layout::Window(0));
+        }
+    }
+
+    fn shift(&mut self, removed: usize) {
+        match self {
+            Layout::Window(index) => {
+                if *index > removed {
+                    *index -= 1;
+                }
+            },
+            Layout::Split { first, second, .. } => {
+                first.shift(removed);
+                second.shift(removed);
+            },
+        }
+    }
+
+    /// Window indices in screen order, used to move the focus around.
+    pub fn windows(&self) -> Vec<usize> {
+        match self {
+            Layout::Window(index) => vec![*index],
+            Layout::Split { first, second, .. } => {
+                let mut windows = first.windows();
+                windows.extend(second.windows());
+                windows
+            },
+        }
+    }
+
+    /// Divides `area` between the windows. Each window's rectangle includes
+    /// its status line. Returns the rectangles and the columns left for the
+    /// separators between side by side windows.
+    pub fn arrange(&self, area: Rect, windows: &mut Vec<(usize, Rect)>, separators: &mut Vec<Rect>) {
+        match self {
+            Layout::Window(index) => windows.push((*index, area)),
+            Layout::Split { direction: SplitDirection::Horizontal, first, second } => {
+                let height = area.height / 2;
+                first.arrange(Rect { height, ..area }, windows, separators);
+                second.arrange(
+                    Rect {
+                        y: area.y + height,
+                        height: area.height - height,
+                        ..area
+                    },
+                    windows,
+                    separators,
+                );
+            },
+            Layout::Split { direction: SplitDirection::Vertical, first, second } => {
+                let width = area.width.saturating_sub(1) / 2;
+                first.arrange(Rect { width, ..area }, windows, separators);
+                separators.push(Rect {
+                    x: area.x + width,
+                    width: 1,
+                    ..area
+                });
+                second.arrange(
+                    Rect {
+                        x: area.x + width + 1,
+                        width: area.width.saturating_sub(width + 1),
+                        ..area
+                    },
+                    windows,
+                    separators,
+                );
+            },
+        }
+    }
+}